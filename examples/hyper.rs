@@ -88,14 +88,14 @@ fn nel_process_response(
     match resp {
         Err(error) => report_error(method, url, 0, error.into()),
         Ok(resp) => {
-            if resp.status() != 200 {
-                // Cloudflare generally ignores "http.error", so we use "http.response.invalid"
-                let error = nel::Error {
-                    class: "http".to_owned(),
-                    subclass: "response.invalid".to_owned(),
-                };
-
-                report_error(method, url, resp.status().as_u16() as usize, error);
+            // Cloudflare generally ignores "http.error", so we use "http.response.invalid"
+            let classifier = nel::ResponseClassifier::new()
+                .http_error(nel::Error::Http(nel::HttpError::ResponseInvalid));
+            let status = resp.status().as_u16();
+            let requested = url.to_string();
+
+            if let Some(error) = classifier.classify(status, resp.headers(), &[&requested], None) {
+                report_error(method, url, status as usize, error);
             }
         }
     };
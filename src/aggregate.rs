@@ -0,0 +1,91 @@
+use crate::report::{AggregationKey, NELReport};
+use std::collections::HashMap;
+
+/// Aggregator collapses identical reports received within a window into a single report.
+pub struct Aggregator {
+    pending: HashMap<AggregationKey, NELReport>,
+    max_pending: usize,
+}
+
+impl Aggregator {
+    pub fn new(max_pending: usize) -> Self {
+        Aggregator {
+            pending: HashMap::new(),
+            max_pending,
+        }
+    }
+
+    /// Adds a report to the current window. If the report can't be buffered because too many
+    /// distinct reports are already pending, it is handed back to be submitted immediately.
+    pub fn add(&mut self, report: NELReport) -> Option<NELReport> {
+        let key = report.aggregation_key();
+        if let Some(existing) = self.pending.get_mut(&key) {
+            existing.merge(&report);
+            return None;
+        }
+        if self.pending.len() >= self.max_pending {
+            return Some(report);
+        }
+        self.pending.insert(key, report);
+        None
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Ends the current window, returning all of the aggregated reports.
+    pub fn drain(&mut self) -> Vec<NELReport> {
+        let mut reports: Vec<NELReport> = self.pending.drain().map(|(_, report)| report).collect();
+        reports.sort_by_key(|report| report.first_seen());
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, TransportError};
+
+    fn report(url: &str) -> NELReport {
+        let mut report = NELReport::new(url.to_string());
+        report.set_server_ip(Some("192.0.2.1:443"));
+        report.set_error(Error::Tcp(TransportError::Refused));
+        report
+    }
+
+    #[test]
+    fn collapses_duplicates() {
+        let mut agg = Aggregator::new(16);
+        let first = report("https://example.com/");
+        let second = report("https://example.com/");
+        assert!(agg.add(first.clone()).is_none());
+        assert!(agg.add(second.clone()).is_none());
+        assert!(agg.add(report("https://example.org/")).is_none());
+
+        let reports = agg.drain();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].count(), 2);
+        assert_eq!(reports[0].first_seen(), first.first_seen());
+        assert_eq!(reports[0].last_seen(), second.last_seen());
+        assert_eq!(reports[1].count(), 1);
+        assert!(agg.is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&reports[0].serialize()).unwrap();
+        assert_eq!(json[0]["body"]["count"], 2);
+        assert!(json[0]["body"]["last_seen_age"].is_u64());
+        assert!(
+            json[0]["body"]["first_seen_age"].as_u64() >= json[0]["body"]["last_seen_age"].as_u64()
+        );
+        let json: serde_json::Value = serde_json::from_str(&reports[1].serialize()).unwrap();
+        assert_eq!(json[0]["body"].get("first_seen_age"), None);
+    }
+
+    #[test]
+    fn overflow_is_returned() {
+        let mut agg = Aggregator::new(1);
+        assert!(agg.add(report("https://example.com/")).is_none());
+        assert!(agg.add(report("https://example.com/")).is_none());
+        assert!(agg.add(report("https://example.org/")).is_some());
+    }
+}
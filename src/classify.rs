@@ -0,0 +1,209 @@
+use crate::error::{Error, HttpError};
+use std::collections::HashSet;
+
+/// The most redirects followed before a chain is treated as a loop, matching Chromium.
+const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+/// ResponseClassifier decides which NEL error type, if any, a received HTTP response stands for.
+#[derive(Debug, Clone)]
+pub struct ResponseClassifier {
+    success_statuses: HashSet<u16>,
+    max_redirects: usize,
+    http_error: Error,
+}
+
+impl Default for ResponseClassifier {
+    fn default() -> Self {
+        ResponseClassifier::new()
+    }
+}
+
+impl ResponseClassifier {
+    pub fn new() -> Self {
+        ResponseClassifier {
+            success_statuses: HashSet::new(),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            http_error: Error::Http(HttpError::Error),
+        }
+    }
+
+    /// Counts responses with this status as successful, even if it's a 4xx or 5xx.
+    pub fn success_status(mut self, status: u16) -> Self {
+        self.success_statuses.insert(status);
+        self
+    }
+
+    /// Sets how many redirects can be followed before the chain is treated as a loop.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sets the error reported for 4xx and 5xx responses, instead of http.error.
+    pub fn http_error(mut self, err: Error) -> Self {
+        self.http_error = err;
+        self
+    }
+
+    /// classify returns the error for a response, or None if it was successful.
+    ///
+    /// A `status` of 0 means that no response was received at all. `redirect_chain` is every URL
+    /// requested to get this response, in order, and `body_len` is the number of body bytes
+    /// received, if the whole body has been read.
+    pub fn classify<I, K, V>(
+        &self,
+        status: u16,
+        headers: I,
+        redirect_chain: &[&str],
+        body_len: Option<u64>,
+    ) -> Option<Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        if status == 0 {
+            return Some(Error::Http(HttpError::ResponseInvalidEmpty));
+        }
+
+        let mut visited = HashSet::new();
+        if redirect_chain.len() > self.max_redirects + 1
+            || !redirect_chain.iter().all(|url| visited.insert(*url))
+        {
+            return Some(Error::Http(HttpError::ResponseRedirectLoop));
+        }
+
+        if let Err(err) = check_headers(headers, body_len) {
+            return Some(Error::Http(err));
+        }
+
+        match status {
+            status if self.success_statuses.contains(&status) => None,
+            200..=399 => None,
+            416 => Some(Error::Http(HttpError::RequestRangeNotSatisfiable)),
+            400..=599 => Some(self.http_error.clone()),
+            _ => Some(Error::Http(HttpError::ResponseInvalid)),
+        }
+    }
+}
+
+/// Looks for headers that make a response invalid, or that disagree with the body received.
+fn check_headers<I, K, V>(headers: I, body_len: Option<u64>) -> Result<(), HttpError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<[u8]>,
+{
+    let mut content_length = None;
+    let mut content_disposition = false;
+    for (name, value) in headers {
+        let name = name.as_ref();
+        if name.eq_ignore_ascii_case("content-length") {
+            let len = std::str::from_utf8(value.as_ref())
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or(HttpError::ResponseInvalid)?;
+            match content_length {
+                Some(existing) if existing != len => {
+                    return Err(HttpError::ResponseHeadersMultipleContentLength)
+                }
+                _ => content_length = Some(len),
+            }
+        } else if name.eq_ignore_ascii_case("content-disposition") {
+            if content_disposition {
+                return Err(HttpError::ResponseHeadersMultipleContentDisposition);
+            }
+            content_disposition = true;
+        }
+    }
+
+    match (content_length, body_len) {
+        (Some(expected), Some(received)) if expected != received => {
+            Err(HttpError::ResponseInvalidContentLengthMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_HEADERS: [(&str, &str); 0] = [];
+
+    #[test]
+    fn statuses() {
+        let classifier = ResponseClassifier::new().success_status(404);
+        let classify = |status| classifier.classify(status, NO_HEADERS, &[], None);
+
+        assert_eq!(classify(200), None);
+        assert_eq!(classify(304), None);
+        assert_eq!(classify(404), None);
+        assert_eq!(classify(500), Some(Error::Http(HttpError::Error)));
+        assert_eq!(
+            classify(416),
+            Some(Error::Http(HttpError::RequestRangeNotSatisfiable))
+        );
+        assert_eq!(
+            classify(0),
+            Some(Error::Http(HttpError::ResponseInvalidEmpty))
+        );
+        assert_eq!(classify(99), Some(Error::Http(HttpError::ResponseInvalid)));
+
+        let classifier =
+            ResponseClassifier::new().http_error(Error::Http(HttpError::ResponseInvalid));
+        assert_eq!(
+            classifier.classify(503, NO_HEADERS, &[], None),
+            Some(Error::Http(HttpError::ResponseInvalid))
+        );
+    }
+
+    #[test]
+    fn redirects() {
+        let classifier = ResponseClassifier::new().max_redirects(2);
+        let loop_err = Some(Error::Http(HttpError::ResponseRedirectLoop));
+
+        let chain = ["https://a.example/", "https://b.example/"];
+        assert_eq!(classifier.classify(200, NO_HEADERS, &chain, None), None);
+
+        let chain = [
+            "https://a.example/",
+            "https://b.example/",
+            "https://a.example/",
+        ];
+        assert_eq!(classifier.classify(302, NO_HEADERS, &chain, None), loop_err);
+
+        let chain = ["/1", "/2", "/3", "/4"];
+        assert_eq!(classifier.classify(200, NO_HEADERS, &chain, None), loop_err);
+    }
+
+    #[test]
+    fn headers() {
+        let classifier = ResponseClassifier::new();
+        let classify = |headers: &[(&str, &str)], body_len| {
+            classifier.classify(200, headers.iter().copied(), &[], body_len)
+        };
+
+        assert_eq!(classify(&[("Content-Length", "5")], Some(5)), None);
+        assert_eq!(
+            classify(&[("Content-Length", "5")], Some(3)),
+            Some(Error::Http(HttpError::ResponseInvalidContentLengthMismatch))
+        );
+        assert_eq!(
+            classify(&[("content-length", "5"), ("content-length", "6")], None),
+            Some(Error::Http(HttpError::ResponseHeadersMultipleContentLength))
+        );
+        assert_eq!(
+            classify(
+                &[
+                    ("content-disposition", "inline"),
+                    ("content-disposition", "attachment")
+                ],
+                None
+            ),
+            Some(Error::Http(
+                HttpError::ResponseHeadersMultipleContentDisposition
+            ))
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use crate::compression::{decompress, ContentEncoding};
+use crate::parse::{parse_reports, ReceivedReport};
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// Default limit on the size of a request body accepted by the collector.
+const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// ReportSink receives the reports accepted by a Collector.
+pub trait ReportSink: Send + Sync + 'static {
+    fn receive(&self, reports: Vec<ReceivedReport>);
+}
+
+impl<F> ReportSink for F
+where
+    F: Fn(Vec<ReceivedReport>) + Send + Sync + 'static,
+{
+    fn receive(&self, reports: Vec<ReceivedReport>) {
+        self(reports)
+    }
+}
+
+/// Collector is an HTTP server that receives NEL reports and hands them to a ReportSink.
+pub struct Collector<S> {
+    sink: Arc<S>,
+    max_body_size: usize,
+}
+
+impl<S: ReportSink> Collector<S> {
+    pub fn new(sink: S) -> Self {
+        Collector {
+            sink: Arc::new(sink),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Sets the largest request body, in bytes, that the collector will accept.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// handle processes a single request to the collector.
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match *req.method() {
+            // CORS preflight from browser reporters.
+            Method::OPTIONS => {
+                let mut resp = respond(StatusCode::NO_CONTENT);
+                let hdrs = resp.headers_mut();
+                hdrs.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static("POST, OPTIONS"),
+                );
+                hdrs.insert(
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    HeaderValue::from_static("content-type, content-encoding"),
+                );
+                hdrs.insert(
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from_static("86400"),
+                );
+                resp
+            }
+            Method::POST => self.receive(req).await,
+            _ => respond(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    async fn receive(&self, req: Request<Body>) -> Response<Body> {
+        if !is_reports_content_type(req.headers().get(header::CONTENT_TYPE)) {
+            return respond(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        let encoding = match content_encoding(req.headers().get(header::CONTENT_ENCODING)) {
+            Ok(encoding) => encoding,
+            Err(status) => return respond(status),
+        };
+
+        let mut body = match read_body(req.into_body(), self.max_body_size).await {
+            Ok(body) => body,
+            Err(status) => return respond(status),
+        };
+        if let Some(encoding) = encoding {
+            body = match decompress(&body, encoding, self.max_body_size) {
+                Ok(body) if body.len() > self.max_body_size => {
+                    return respond(StatusCode::PAYLOAD_TOO_LARGE)
+                }
+                Ok(body) => body,
+                Err(_) => return respond(StatusCode::BAD_REQUEST),
+            };
+        }
+        match parse_reports(&body) {
+            Ok(reports) => {
+                self.sink.receive(reports);
+                respond(StatusCode::NO_CONTENT)
+            }
+            Err(_) => respond(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    /// serve runs the collector on the given listener until an error occurs.
+    pub async fn serve(self, listener: TcpListener) -> hyper::Result<()> {
+        let _ = listener.set_nonblocking(true);
+        let collector = Arc::new(self);
+        let make_svc = make_service_fn(move |_| {
+            let collector = collector.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let collector = collector.clone();
+                    async move { Ok::<_, Infallible>(collector.handle(req).await) }
+                }))
+            }
+        });
+
+        Server::from_tcp(listener)?.serve(make_svc).await
+    }
+}
+
+fn respond(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    resp
+}
+
+fn is_reports_content_type(value: Option<&HeaderValue>) -> bool {
+    let value = match value.and_then(|value| value.to_str().ok()) {
+        Some(value) => value,
+        None => return false,
+    };
+    let mime = value.split(';').next().unwrap_or("").trim();
+    mime.eq_ignore_ascii_case("application/reports+json")
+        || mime.eq_ignore_ascii_case("application/json")
+}
+
+/// Returns the encoding a request body was compressed with, or None if it wasn't. Encodings that
+/// can't be decoded are rejected with 415.
+fn content_encoding(value: Option<&HeaderValue>) -> Result<Option<ContentEncoding>, StatusCode> {
+    let value = match value {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| StatusCode::UNSUPPORTED_MEDIA_TYPE)?,
+        None => return Ok(None),
+    };
+    if value.trim().eq_ignore_ascii_case("identity") {
+        return Ok(None);
+    }
+    ContentEncoding::parse(value)
+        .map(Some)
+        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+}
+
+/// Reads the whole request body, failing if it's larger than `limit` bytes.
+async fn read_body(mut body: Body, limit: usize) -> Result<Vec<u8>, StatusCode> {
+    if let Some(len) = body.size_hint().exact() {
+        if len as usize > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if buf.len() + chunk.len() > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, TransportError};
+    use crate::tests::QUEUE;
+    use crate::{
+        handle_reports_with_config, nel_header, report_to_header, submit_report, Compression,
+        NELReport, Payload, ReportingConfig,
+    };
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn preflight_and_limits() {
+        let collector = Collector::new(|_: Vec<ReceivedReport>| {}).max_body_size(16);
+
+        let req = Request::options("/").body(Body::empty()).unwrap();
+        let resp = collector.handle(req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        let req = Request::post("/")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("[]"))
+            .unwrap();
+        let resp = collector.handle(req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let req = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/reports+json")
+            .body(Body::from(vec![b' '; 17]))
+            .unwrap();
+        let resp = collector.handle(req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn decodes_compressed_bodies() {
+        let body = r#"[{"age":0,"type":"network-error","url":"https://example.com/",
+            "body":{"phase":"connection","type":"tcp.refused"}}]"#;
+        let payload = Payload::encode(
+            body.to_string(),
+            Some(&Compression {
+                encoding: ContentEncoding::Gzip,
+                min_size: 0,
+            }),
+        );
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let collector = Collector::new(|_: Vec<ReceivedReport>| {});
+        let post = |encoding: &str, body: Vec<u8>| {
+            let req = Request::post("/")
+                .header(header::CONTENT_TYPE, "application/reports+json")
+                .header(header::CONTENT_ENCODING, encoding)
+                .body(Body::from(body))
+                .unwrap();
+            rt.block_on(collector.handle(req)).status()
+        };
+        assert_eq!(post("gzip", payload.body.clone()), StatusCode::NO_CONTENT);
+        assert_eq!(post("identity", body.into()), StatusCode::NO_CONTENT);
+        assert_eq!(post("gzip", body.into()), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            post("compress", payload.body),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    /// Submits a report about `host`, and returns it as received by a collector, along with the
+    /// content encoding it was sent with.
+    fn receive_own_report(
+        host: &str,
+        compression: Option<Compression>,
+    ) -> (Vec<ReceivedReport>, Vec<Option<ContentEncoding>>) {
+        let _guard = QUEUE.lock().unwrap_or_else(|err| err.into_inner());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let sink = {
+                let received = received.clone();
+                move |reports: Vec<ReceivedReport>| received.lock().unwrap().extend(reports)
+            };
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(Collector::new(sink).serve(listener));
+
+            nel_header(host, r#"{"report_to":"default","max_age":60}"#);
+            report_to_header(
+                host,
+                &format!(
+                    r#"{{"group":"default","max_age":60,"endpoints":[{{"url":"http://{}/"}}]}}"#,
+                    addr
+                ),
+            );
+
+            let mut report = NELReport::new(format!("https://{}/", host));
+            report.set_error(Error::Tcp(TransportError::Refused));
+            submit_report(report);
+
+            let encodings = Arc::new(Mutex::new(Vec::new()));
+            let config = ReportingConfig {
+                compression,
+                ..Default::default()
+            };
+            let client = reqwest::Client::new();
+            let post = {
+                let encodings = encodings.clone();
+                move |uri, payload: Payload| {
+                    encodings.lock().unwrap().push(payload.content_encoding);
+                    let mut req = client
+                        .post(uri)
+                        .header(header::CONTENT_TYPE, "application/reports+json");
+                    if let Some(encoding) = payload.content_encoding {
+                        req = req.header(header::CONTENT_ENCODING, encoding.as_str());
+                    }
+                    let req = req.body(payload.body);
+                    async move { req.send().await.is_ok() }
+                }
+            };
+            tokio::spawn(handle_reports_with_config(config, tokio::time::sleep, post));
+
+            for _ in 0..500 {
+                if !received.lock().unwrap().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            let received = received.lock().unwrap().clone();
+            let encodings = encodings.lock().unwrap().clone();
+            (received, encodings)
+        })
+    }
+
+    #[test]
+    fn receives_own_reports() {
+        let host = "collector-test.example";
+        let (received, encodings) = receive_own_report(host, None);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].url, format!("https://{}/", host));
+        assert_eq!(received[0].error, Some(Error::Tcp(TransportError::Refused)));
+        assert_eq!(encodings, vec![None]);
+    }
+
+    #[test]
+    fn receives_own_compressed_reports() {
+        let encodings = [
+            ContentEncoding::Gzip,
+            #[cfg(feature = "brotli-compression")]
+            ContentEncoding::Brotli,
+        ];
+
+        for (i, &encoding) in encodings.iter().enumerate() {
+            let host = format!("collector-compressed-test-{}.example", i);
+            let compression = Compression {
+                encoding,
+                min_size: 0,
+            };
+            let (received, sent) = receive_own_report(&host, Some(compression));
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].url, format!("https://{}/", host));
+            assert_eq!(sent, vec![Some(encoding)]);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::Write;
+
+/// ContentEncoding is a compression scheme that report payloads can be encoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    Gzip,
+    #[cfg(feature = "brotli-compression")]
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// Returns the value of the Content-Encoding header for this encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            #[cfg(feature = "brotli-compression")]
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// Parses the value of a Content-Encoding header, returning None if the encoding isn't
+    /// supported.
+    #[cfg(feature = "collector")]
+    pub(crate) fn parse(value: &str) -> Option<ContentEncoding> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" => Some(ContentEncoding::Gzip),
+            #[cfg(feature = "brotli-compression")]
+            "br" => Some(ContentEncoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+/// Compression configures how report payloads are compressed before being submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Compression {
+    pub encoding: ContentEncoding,
+    /// Payloads smaller than this many bytes are sent uncompressed.
+    pub min_size: usize,
+}
+
+/// Payload is the body of a POST request to a reporting endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+    pub body: Vec<u8>,
+    /// The encoding the body was compressed with, to be sent in the Content-Encoding header. If
+    /// None, the body is uncompressed JSON.
+    pub content_encoding: Option<ContentEncoding>,
+}
+
+impl Payload {
+    /// Compresses the serialized reports if they're large enough to be worth it.
+    pub(crate) fn encode(json: String, compression: Option<&Compression>) -> Payload {
+        let compression = match compression {
+            Some(compression) if json.len() >= compression.min_size => compression,
+            _ => return Payload::identity(json),
+        };
+
+        match compress(json.as_bytes(), compression.encoding) {
+            Ok(body) => Payload {
+                body,
+                content_encoding: Some(compression.encoding),
+            },
+            Err(_) => Payload::identity(json),
+        }
+    }
+
+    fn identity(json: String) -> Payload {
+        Payload {
+            body: json.into_bytes(),
+            content_encoding: None,
+        }
+    }
+}
+
+fn compress(input: &[u8], encoding: ContentEncoding) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(input)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "brotli-compression")]
+        ContentEncoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            encoder.write_all(input)?;
+            encoder.flush()?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+/// Decompresses a payload. To bound the memory a malicious payload can use, decompression stops
+/// after `limit` bytes plus one, so that the caller can tell whether the limit was exceeded.
+#[cfg(feature = "collector")]
+pub(crate) fn decompress(
+    input: &[u8],
+    encoding: ContentEncoding,
+    limit: usize,
+) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let decoder: Box<dyn Read + '_> = match encoding {
+        ContentEncoding::Gzip => Box::new(flate2::read::GzDecoder::new(input)),
+        #[cfg(feature = "brotli-compression")]
+        ContentEncoding::Brotli => Box::new(brotli::Decompressor::new(input, 4096)),
+    };
+
+    let mut output = Vec::new();
+    decoder.take(limit as u64 + 1).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const REPORTS: &str = r#"[{"age":0,"type":"network-error","url":"https://example.com/"}]"#;
+
+    #[test]
+    fn small_payloads_are_not_compressed() {
+        let compression = Compression {
+            encoding: ContentEncoding::Gzip,
+            min_size: 1024,
+        };
+        let payload = Payload::encode(REPORTS.to_string(), Some(&compression));
+        assert_eq!(payload.content_encoding, None);
+        assert_eq!(payload.body, REPORTS.as_bytes());
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let compression = Compression {
+            encoding: ContentEncoding::Gzip,
+            min_size: 0,
+        };
+        let payload = Payload::encode(REPORTS.to_string(), Some(&compression));
+        assert_eq!(payload.content_encoding, Some(ContentEncoding::Gzip));
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&payload.body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, REPORTS);
+    }
+
+    #[cfg(feature = "brotli-compression")]
+    #[test]
+    fn brotli_round_trip() {
+        let compression = Compression {
+            encoding: ContentEncoding::Brotli,
+            min_size: 0,
+        };
+        let payload = Payload::encode(REPORTS.to_string(), Some(&compression));
+        assert_eq!(payload.content_encoding, Some(ContentEncoding::Brotli));
+
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&payload.body[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, REPORTS);
+    }
+}
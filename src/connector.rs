@@ -0,0 +1,461 @@
+use crate::{NELReport, Stage};
+use futures_util::future::BoxFuture;
+use hyper::client::connect::dns::Name;
+use hyper::client::connect::{Connected, Connection, HttpInfo};
+use hyper::http::Extensions;
+use hyper::Uri;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower_service::Service;
+
+thread_local! {
+    /// The tracker of the request whose future is currently being polled on this thread.
+    static CURRENT: RefCell<Option<ConnectTracker>> = const { RefCell::new(None) };
+}
+
+/// ConnectInfo is what was observed while a connection was being opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectInfo {
+    /// The IP address connected to, or the first one resolved if the connection failed.
+    pub server_ip: Option<IpAddr>,
+    /// The ALPN protocol ID of the connection.
+    pub protocol: Option<&'static str>,
+    pub dns_time: Option<Duration>,
+    pub tcp_time: Option<Duration>,
+    pub tls_time: Option<Duration>,
+    /// How far the request got. Once the connection is open this is
+    /// [`Stage::AwaitingResponse`], until the tracker is told otherwise.
+    pub stage: Option<Stage>,
+}
+
+impl ConnectInfo {
+    /// Copies the server IP, protocol and stage into a report, where they're known.
+    pub fn apply(&self, report: &mut NELReport) {
+        // set_server_ip expects an address that may have a port, which would cut an IPv6
+        // address short.
+        if let Some(server_ip) = self.server_ip {
+            report.server_ip = server_ip.to_string();
+        }
+        if let Some(protocol) = self.protocol {
+            report.set_protocol(Some(protocol));
+        }
+        if let Some(stage) = self.stage {
+            report.set_stage(stage);
+        }
+    }
+}
+
+/// When each step of opening a connection finished.
+#[derive(Debug, Default)]
+struct Timeline {
+    start: Option<Instant>,
+    dns_start: Option<Instant>,
+    dns_end: Option<Instant>,
+    resolved_ip: Option<IpAddr>,
+    /// When each NelConnector finished connecting, innermost first.
+    connected: Vec<Instant>,
+    server_ip: Option<IpAddr>,
+    protocol: Option<&'static str>,
+    /// How many NelConnectors are still connecting.
+    pending: usize,
+    stage: Option<Stage>,
+}
+
+impl Timeline {
+    fn info(&self) -> ConnectInfo {
+        let tcp_start = self.dns_end.or(self.start);
+        let tcp_end = self.connected.first().copied();
+        ConnectInfo {
+            server_ip: self.server_ip.or(self.resolved_ip),
+            protocol: self.protocol,
+            dns_time: self
+                .dns_start
+                .zip(self.dns_end)
+                .map(|(start, end)| end - start),
+            tcp_time: tcp_start.zip(tcp_end).map(|(start, end)| end - start),
+            tls_time: match self.connected.as_slice() {
+                [tcp_end, .., tls_end] => Some(*tls_end - *tcp_end),
+                _ => None,
+            },
+            stage: self.stage,
+        }
+    }
+}
+
+/// ConnectTracker collects what NelConnector and NelResolver observe while a request's
+/// connection is opened, so that it's available even if the request fails.
+///
+/// Use one tracker per request, and poll the request's future with [`ConnectTracker::track`].
+#[derive(Debug, Clone, Default)]
+pub struct ConnectTracker {
+    timeline: Arc<Mutex<Timeline>>,
+}
+
+impl ConnectTracker {
+    pub fn new() -> Self {
+        ConnectTracker::default()
+    }
+
+    /// Wraps a request's future, so that connections opened while polling it are tracked.
+    pub fn track<F: Future>(&self, fut: F) -> Tracked<F> {
+        Tracked {
+            fut: Box::pin(fut),
+            tracker: self.clone(),
+        }
+    }
+
+    /// Returns what has been observed about the connection so far.
+    pub fn info(&self) -> ConnectInfo {
+        self.with_timeline(|timeline| timeline.info())
+            .unwrap_or_default()
+    }
+
+    /// Records that the request has moved on to a stage that connectors can't see, such as
+    /// reading the response body.
+    pub fn set_stage(&self, stage: Stage) {
+        self.with_timeline(|timeline| timeline.stage = Some(stage));
+    }
+
+    /// Returns the tracker of the request being polled on this thread, if any.
+    fn current() -> Option<ConnectTracker> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    fn with_timeline<T>(&self, f: impl FnOnce(&mut Timeline) -> T) -> Option<T> {
+        self.timeline.lock().ok().map(|mut guard| f(&mut guard))
+    }
+
+    /// Records that a NelConnector finished connecting.
+    fn connected(&self, connected: &Connected) -> ConnectInfo {
+        let mut extensions = Extensions::new();
+        connected.get_extras(&mut extensions);
+        let server_ip = extensions
+            .get::<HttpInfo>()
+            .map(|info| info.remote_addr().ip());
+        let protocol = if connected.is_negotiated_h2() {
+            "h2"
+        } else {
+            "http/1.1"
+        };
+
+        self.with_timeline(|timeline| {
+            timeline.connected.push(Instant::now());
+            timeline.pending = timeline.pending.saturating_sub(1);
+            // Outer connectors are still doing their handshakes over this connection.
+            timeline.stage = Some(if timeline.pending > 0 {
+                Stage::Handshaking
+            } else {
+                Stage::AwaitingResponse
+            });
+            timeline.server_ip = server_ip.or(timeline.server_ip);
+            timeline.protocol = Some(protocol);
+            timeline.info()
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// Tracked is a future that makes its tracker current while it's polled.
+pub struct Tracked<F> {
+    fut: Pin<Box<F>>,
+    tracker: ConnectTracker,
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let prev = CURRENT.with(|current| current.replace(Some(this.tracker.clone())));
+        let _restore = Restore(prev);
+        this.fut.as_mut().poll(cx)
+    }
+}
+
+/// Puts back the previously current tracker, even if polling panics.
+struct Restore(Option<ConnectTracker>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+/// NelConnector wraps a hyper connector to record how long connecting took, the IP address
+/// connected to and the negotiated protocol.
+///
+/// Wrapping both the TCP connector and the TLS connector around it also separates TCP from
+/// TLS time, and wrapping the TCP connector's resolver in [`NelResolver`] records DNS time:
+///
+/// ```ignore
+/// let http = HttpConnector::new_with_resolver(NelResolver::new(GaiResolver::new()));
+/// let https = HttpsConnector::from((NelConnector::new(http), tls));
+/// let client = Client::builder().build(NelConnector::new(https));
+/// ```
+///
+/// The result is added to the extensions of responses as a [`ConnectInfo`], and to the
+/// [`ConnectTracker`] of the request that opened the connection.
+#[derive(Debug, Clone)]
+pub struct NelConnector<C> {
+    inner: C,
+}
+
+impl<C> NelConnector<C> {
+    pub fn new(inner: C) -> Self {
+        NelConnector { inner }
+    }
+}
+
+impl<C> Service<Uri> for NelConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: Connection + Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Send + 'static,
+{
+    type Response = NelConnection<C::Response>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let tracker = ConnectTracker::current().unwrap_or_default();
+        tracker.with_timeline(|timeline| {
+            timeline.start.get_or_insert_with(Instant::now);
+            timeline.pending += 1;
+            timeline.stage = Some(Stage::Connecting);
+        });
+        // hyper may poll the connection attempt in the background, so inner connectors and
+        // resolvers need the tracker made current again.
+        let fut = tracker.track(self.inner.call(dst));
+
+        Box::pin(async move {
+            let inner = fut.await?;
+            let info = tracker.connected(&inner.connected());
+            Ok(NelConnection { inner, info })
+        })
+    }
+}
+
+/// NelConnection is a connection opened by NelConnector.
+#[derive(Debug)]
+pub struct NelConnection<T> {
+    inner: T,
+    info: ConnectInfo,
+}
+
+impl<T> NelConnection<T> {
+    /// Returns what was observed while the connection was opened.
+    pub fn info(&self) -> &ConnectInfo {
+        &self.info
+    }
+}
+
+impl<T: Connection> Connection for NelConnection<T> {
+    fn connected(&self) -> Connected {
+        self.inner.connected().extra(self.info.clone())
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for NelConnection<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for NelConnection<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+/// NelResolver wraps a hyper resolver to record how long DNS resolution took, and the first
+/// address it returned.
+#[derive(Debug, Clone)]
+pub struct NelResolver<R> {
+    inner: R,
+}
+
+impl<R> NelResolver<R> {
+    pub fn new(inner: R) -> Self {
+        NelResolver { inner }
+    }
+}
+
+impl<R> Service<Name> for NelResolver<R>
+where
+    R: Service<Name>,
+    R::Response: Iterator<Item = SocketAddr>,
+    R::Future: Send + 'static,
+    R::Error: Send + 'static,
+{
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = R::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let tracker = ConnectTracker::current();
+        if let Some(tracker) = &tracker {
+            tracker.with_timeline(|timeline| {
+                timeline.dns_start = Some(Instant::now());
+                timeline.stage = Some(Stage::Resolving);
+            });
+        }
+        let fut = self.inner.call(name);
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = fut.await?.collect();
+            if let Some(tracker) = &tracker {
+                tracker.with_timeline(|timeline| {
+                    timeline.dns_end = Some(Instant::now());
+                    timeline.resolved_ip = addrs.first().map(SocketAddr::ip);
+                    timeline.stage = Some(Stage::Connecting);
+                });
+            }
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Phase, TransportError};
+    use futures_util::future::{ready, Ready};
+    use hyper::client::connect::HttpConnector;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    /// Resolves every name to one address.
+    #[derive(Clone)]
+    struct StaticResolver(SocketAddr);
+
+    impl Service<Name> for StaticResolver {
+        type Response = std::iter::Once<SocketAddr>;
+        type Error = io::Error;
+        type Future = Ready<io::Result<Self::Response>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Name) -> Self::Future {
+            ready(Ok(std::iter::once(self.0)))
+        }
+    }
+
+    fn http_connector(addr: SocketAddr) -> HttpConnector<NelResolver<StaticResolver>> {
+        HttpConnector::new_with_resolver(NelResolver::new(StaticResolver(addr)))
+    }
+
+    fn uri(addr: SocketAddr) -> Uri {
+        format!("http://example.test:{}/", addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { listener.accept().await });
+
+        // The outer connector stands in for a TLS connector.
+        let connector = NelConnector::new(NelConnector::new(http_connector(addr)));
+        let tracker = ConnectTracker::new();
+        let conn = tracker.track(connector.oneshot(uri(addr))).await.unwrap();
+
+        let info = tracker.info();
+        assert_eq!(info.server_ip, Some(addr.ip()));
+        assert_eq!(info.protocol, Some("http/1.1"));
+        assert!(info.dns_time.is_some());
+        assert!(info.tcp_time.is_some());
+        assert!(info.tls_time.is_some());
+        assert_eq!(info.stage, Some(Stage::AwaitingResponse));
+
+        let mut extensions = Extensions::new();
+        conn.connected().get_extras(&mut extensions);
+        assert_eq!(extensions.get::<ConnectInfo>(), Some(&info));
+
+        let mut report = NELReport::new(uri(addr).to_string());
+        info.apply(&mut report);
+        assert_eq!(report.server_ip, "127.0.0.1");
+        assert_eq!(report.protocol, "http/1.1");
+
+        // The connection was open, so a reset happened after the request was sent.
+        report.set_error(Error::Tcp(TransportError::Reset));
+        assert_eq!(report.phase(), Phase::Application);
+    }
+
+    #[test]
+    fn applies_ipv6_addresses() {
+        let info = ConnectInfo {
+            server_ip: Some("2001:db8::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut report = NELReport::new("https://example.test/".to_string());
+        info.apply(&mut report);
+        assert_eq!(report.server_ip, "2001:db8::1");
+    }
+
+    #[tokio::test]
+    async fn records_failed_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let connector = NelConnector::new(http_connector(addr));
+        let tracker = ConnectTracker::new();
+        let result = tracker.track(connector.oneshot(uri(addr))).await;
+        assert!(result.is_err());
+
+        let info = tracker.info();
+        assert_eq!(info.server_ip, Some(addr.ip()));
+        assert_eq!(info.protocol, None);
+        assert!(info.dns_time.is_some());
+        assert!(info.tcp_time.is_none());
+        assert_eq!(info.stage, Some(Stage::Connecting));
+    }
+}
@@ -1,85 +1,543 @@
+#[cfg(feature = "h2-error")]
+mod h2;
+#[cfg(feature = "h3-error")]
+mod h3;
+#[cfg(feature = "hickory-error")]
+mod hickory;
+#[cfg(feature = "hyper1-error")]
+mod hyper1;
+#[cfg(feature = "native-tls-error")]
+mod native_tls;
+#[cfg(all(
+    feature = "openssl-error",
+    not(any(target_os = "macos", target_os = "ios", windows))
+))]
+mod openssl;
+#[cfg(feature = "quinn-error")]
+mod quinn;
 #[cfg(feature = "reqwest-error")]
 mod reqwest;
 
 #[cfg(feature = "reqwest-error")]
+#[allow(unused_imports)]
 pub use self::reqwest::*;
+#[cfg(feature = "reqwest012-error")]
+mod reqwest012;
+mod rustls;
 
+use crate::parse::ParseError;
+use std::str::FromStr;
+
+/// Phase is the stage of a request at which a network error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    Dns,
+    Connection,
+    Application,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Dns => "dns",
+            Phase::Connection => "connection",
+            Phase::Application => "application",
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Stage is how far through its lifecycle a request had got, as observed by a connection
+/// wrapper or middleware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Resolving,
+    Connecting,
+    Handshaking,
+    AwaitingResponse,
+    ReadingBody,
+}
+
+impl Stage {
+    /// Returns the phase that failures during this stage belong to.
+    pub fn phase(&self) -> Phase {
+        match self {
+            Stage::Resolving => Phase::Dns,
+            Stage::Connecting | Stage::Handshaking => Phase::Connection,
+            Stage::AwaitingResponse | Stage::ReadingBody => Phase::Application,
+        }
+    }
+}
+
+impl FromStr for Phase {
+    type Err = ParseError;
+
+    fn from_str(phase: &str) -> Result<Self, Self::Err> {
+        match phase {
+            "dns" => Ok(Phase::Dns),
+            "connection" => Ok(Phase::Connection),
+            "application" => Ok(Phase::Application),
+            _ => Err(ParseError::InvalidPhase(phase.to_string())),
+        }
+    }
+}
+
+/// Defines an enum of the error subtypes within one class, along with their names in the spec.
+macro_rules! error_types {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $str:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// Returns the part of the error type following the class.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $str,)*
+                }
+            }
+
+            fn parse(subclass: &str) -> Option<Self> {
+                match subclass {
+                    $($str => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+error_types! {
+    /// DnsError is a failure to resolve the origin's address.
+    DnsError {
+        Unreachable => "unreachable",
+        NameNotResolved => "name_not_resolved",
+        Failed => "failed",
+        AddressChanged => "address_changed",
+    }
+}
+
+error_types! {
+    /// TransportError is a failure of the TCP or UDP connection to the origin.
+    TransportError {
+        TimedOut => "timed_out",
+        Closed => "closed",
+        Reset => "reset",
+        Refused => "refused",
+        Aborted => "aborted",
+        AddressInvalid => "address_invalid",
+        AddressUnreachable => "address_unreachable",
+        Failed => "failed",
+    }
+}
+
+error_types! {
+    /// TlsError is a failure to establish a secure connection with the origin.
+    TlsError {
+        VersionOrCipherMismatch => "version_or_cipher_mismatch",
+        BadClientAuthCert => "bad_client_auth_cert",
+        CertNameInvalid => "cert.name_invalid",
+        CertDateInvalid => "cert.date_invalid",
+        CertAuthorityInvalid => "cert.authority_invalid",
+        CertInvalid => "cert.invalid",
+        CertRevoked => "cert.revoked",
+        CertPinnedKeyNotInCertChain => "cert.pinned_key_not_in_cert_chain",
+        ProtocolError => "protocol.error",
+        Failed => "failed",
+    }
+}
+
+error_types! {
+    /// HttpError is a failure in the HTTP exchange with the origin.
+    HttpError {
+        Error => "error",
+        ProtocolError => "protocol.error",
+        ResponseInvalid => "response.invalid",
+        ResponseRedirectLoop => "response.redirect_loop",
+        Failed => "failed",
+        ResponseInvalidEmpty => "response.invalid.empty",
+        ResponseInvalidContentLengthMismatch => "response.invalid.content_length_mismatch",
+        ResponseInvalidIncompleteChunkedEncoding => "response.invalid.incomplete_chunked_encoding",
+        ResponseInvalidInvalidChunkedEncoding => "response.invalid.invalid_chunked_encoding",
+        RequestRangeNotSatisfiable => "request.range_not_satisfiable",
+        ResponseHeadersTruncated => "response.headers.truncated",
+        ResponseHeadersMultipleContentDisposition => "response.headers.multiple_content_disposition",
+        ResponseHeadersMultipleContentLength => "response.headers.multiple_content_length",
+    }
+}
+
+/// Error is a network error type, as defined by the NEL spec.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Error {
-    pub class: String,
-    pub subclass: String,
+pub enum Error {
+    Dns(DnsError),
+    Tcp(TransportError),
+    Udp(TransportError),
+    Tls(TlsError),
+    Http(HttpError),
+    /// The user aborted the request before it completed.
+    Abandoned,
+    /// The cause of the failure couldn't be determined.
+    Unknown,
+    /// An error type that isn't defined by the spec, reported in the given phase.
+    Custom {
+        phase: Phase,
+        error_type: String,
+    },
 }
 
 impl Error {
-    fn new<C, S>(class: C, subclass: S) -> Error
-    where
-        C: std::fmt::Display,
-        S: std::fmt::Display,
-    {
-        Error {
-            class: class.to_string(),
-            subclass: subclass.to_string(),
+    /// Returns an error type that isn't defined by the spec.
+    pub fn custom<T: ToString>(phase: Phase, error_type: T) -> Error {
+        Error::Custom {
+            phase,
+            error_type: error_type.to_string(),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        match self {
+            Error::Dns(_) => Phase::Dns,
+            Error::Tcp(_) | Error::Udp(_) | Error::Tls(_) => Phase::Connection,
+            Error::Http(_) | Error::Abandoned | Error::Unknown => Phase::Application,
+            Error::Custom { phase, .. } => *phase,
         }
     }
 
-    pub fn phase(&self) -> String {
-        match self.class.as_ref() {
-            "dns" => "dns",
-            "tcp" => "connection",
-            "udp" => "connection",
-            "tls" => "connection",
-            "http" => "application",
-            "abandoned" => "application",
-            _ => "unknown",
+    /// Returns the phase of an error that was observed at the given stage of a request. An error
+    /// can't be from an earlier phase than the stage it was seen at, so a tcp.reset after the
+    /// request was sent is in the application phase. Errors that could happen anywhere take
+    /// the stage's phase. DNS errors always stay in the dns phase, which the spec pairs them
+    /// with, since a connector that doesn't see resolution may already report the request as
+    /// connecting.
+    pub fn phase_at(&self, stage: Stage) -> Phase {
+        match self {
+            Error::Custom { phase, .. } => *phase,
+            Error::Dns(_) => Phase::Dns,
+            Error::Abandoned | Error::Unknown => stage.phase(),
+            _ => self.phase().max(stage.phase()),
         }
-        .to_string()
     }
 }
 
-impl ToString for Error {
-    fn to_string(&self) -> String {
-        if self.class == "unknown" {
-            "unknown".to_string()
-        } else if self.class == "abandoned" {
-            "abandoned".to_string()
-        } else {
-            format!("{}.{}", self.class, self.subclass)
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Dns(err) => write!(f, "dns.{}", err.as_str()),
+            Error::Tcp(err) => write!(f, "tcp.{}", err.as_str()),
+            Error::Udp(err) => write!(f, "udp.{}", err.as_str()),
+            Error::Tls(err) => write!(f, "tls.{}", err.as_str()),
+            Error::Http(err) => write!(f, "http.{}", err.as_str()),
+            Error::Abandoned => f.write_str("abandoned"),
+            Error::Unknown => f.write_str("unknown"),
+            Error::Custom { error_type, .. } => f.write_str(error_type),
         }
     }
 }
 
+/// Parses one of the error types defined by the spec. Custom types aren't recognized, because
+/// their phase can't be known.
+impl FromStr for Error {
+    type Err = ParseError;
+
+    fn from_str(error_type: &str) -> Result<Self, Self::Err> {
+        let (class, subclass) = error_type.split_once('.').unwrap_or((error_type, ""));
+        let err = match class {
+            "dns" => DnsError::parse(subclass).map(Error::Dns),
+            "tcp" => TransportError::parse(subclass).map(Error::Tcp),
+            "udp" => TransportError::parse(subclass).map(Error::Udp),
+            "tls" => TlsError::parse(subclass).map(Error::Tls),
+            "http" => HttpError::parse(subclass).map(Error::Http),
+            "abandoned" if subclass.is_empty() => Some(Error::Abandoned),
+            "unknown" if subclass.is_empty() => Some(Error::Unknown),
+            _ => None,
+        };
+        err.ok_or_else(|| ParseError::InvalidErrorType(error_type.to_string()))
+    }
+}
+
+/// OS error codes that identify specific connection failures.
+#[cfg(unix)]
+mod os {
+    pub use libc::{
+        EADDRNOTAVAIL, ECONNABORTED, ECONNREFUSED, ECONNRESET, EHOSTDOWN, EHOSTUNREACH, ENETDOWN,
+        ENETUNREACH, ENOTCONN, EPIPE, ETIMEDOUT,
+    };
+}
+
+/// OS error codes that identify specific connection failures.
+#[cfg(windows)]
+mod os {
+    pub const EADDRNOTAVAIL: i32 = 10049; // WSAEADDRNOTAVAIL
+    pub const ECONNABORTED: i32 = 10053; // WSAECONNABORTED
+    pub const ECONNREFUSED: i32 = 10061; // WSAECONNREFUSED
+    pub const ECONNRESET: i32 = 10054; // WSAECONNRESET
+    pub const EHOSTDOWN: i32 = 10064; // WSAEHOSTDOWN
+    pub const EHOSTUNREACH: i32 = 10065; // WSAEHOSTUNREACH
+    pub const ENETDOWN: i32 = 10050; // WSAENETDOWN
+    pub const ENETUNREACH: i32 = 10051; // WSAENETUNREACH
+    pub const ENOTCONN: i32 = 10057; // WSAENOTCONN
+    pub const EPIPE: i32 = 109; // ERROR_BROKEN_PIPE
+    pub const ETIMEDOUT: i32 = 10060; // WSAETIMEDOUT
+}
+
+/// Maps an OS error code from a socket operation to a transport error.
+#[cfg(any(unix, windows))]
+fn transport_error(code: i32) -> Option<TransportError> {
+    use os::*;
+
+    let err = match code {
+        ETIMEDOUT => TransportError::TimedOut,
+        ECONNRESET => TransportError::Reset,
+        ECONNREFUSED => TransportError::Refused,
+        ECONNABORTED => TransportError::Aborted,
+        EPIPE | ENOTCONN => TransportError::Closed,
+        EADDRNOTAVAIL => TransportError::AddressInvalid,
+        EHOSTUNREACH | ENETUNREACH | EHOSTDOWN | ENETDOWN => TransportError::AddressUnreachable,
+        _ => return None,
+    };
+    Some(err)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn transport_error(_code: i32) -> Option<TransportError> {
+    None
+}
+
 impl From<&std::io::Error> for Error {
     fn from(err: &std::io::Error) -> Self {
         use std::io::ErrorKind;
 
+        // Errors from the OS carry a code that tells us exactly what went wrong.
+        if let Some(code) = err.raw_os_error() {
+            if let Some(transport) = transport_error(code) {
+                return Error::Tcp(transport);
+            }
+        }
+
+        // TLS failures from rustls are wrapped in an I/O error.
+        if let Some(tls_err) = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<::rustls::Error>())
+        {
+            return tls_err.into();
+        }
+
+        // So are DNS failures from hickory-resolver.
+        #[cfg(feature = "hickory-error")]
+        if let Some(dns_err) = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<::hickory_resolver::error::ResolveError>())
+        {
+            return dns_err.into();
+        }
+
         match err.kind() {
-            ErrorKind::TimedOut => Error::new("tcp", "timed_out"),
-            ErrorKind::ConnectionReset => Error::new("tcp", "reset"),
-            ErrorKind::ConnectionRefused => Error::new("tcp", "refused"),
-            ErrorKind::ConnectionAborted => Error::new("tcp", "aborted"),
-
-            _ => match err.to_string().to_lowercase() {
-                str if str.contains("no address") || str.contains("name or service not known") => {
-                    Error::new("dns", "name_not_resolved")
-                }
-                str if str.contains("no route to host") => Error::new("tcp", "address_unreachable"),
-                str if str.contains("unreachable") => Error::new("tcp", "address_unreachable"),
-                str if str.contains("expired") => Error::new("tls", "cert.date_invalid"),
-                str if str.contains("unknownissuer") => Error::new("tls", "cert.authority_invalid"),
-                str if str.contains("certnotvalidforname") => {
-                    Error::new("tls", "cert.name_invalid")
+            ErrorKind::TimedOut => return Error::Tcp(TransportError::TimedOut),
+            ErrorKind::ConnectionReset => return Error::Tcp(TransportError::Reset),
+            ErrorKind::ConnectionRefused => return Error::Tcp(TransportError::Refused),
+            ErrorKind::ConnectionAborted => return Error::Tcp(TransportError::Aborted),
+            ErrorKind::BrokenPipe | ErrorKind::NotConnected | ErrorKind::UnexpectedEof => {
+                return Error::Tcp(TransportError::Closed)
+            }
+            ErrorKind::AddrNotAvailable => return Error::Tcp(TransportError::AddressInvalid),
+            _ => {}
+        }
+
+        // The resolver in std reports getaddrinfo failures without an error code, so the best we
+        // can do is look at the message.
+        let msg = err.to_string().to_lowercase();
+        if msg.contains("temporary failure in name resolution") {
+            return Error::Dns(DnsError::Unreachable);
+        } else if msg.contains("name or service not known")
+            || msg.contains("nodename nor servname")
+            || msg.contains("no address")
+        {
+            return Error::Dns(DnsError::NameNotResolved);
+        } else if msg.contains("failed to lookup address") {
+            return Error::Dns(DnsError::Failed);
+        }
+
+        match err.get_ref() {
+            None => Error::Tcp(TransportError::Failed),
+            Some(_) => Error::Unknown,
+        }
+    }
+}
+
+/// Maps a TLS alert received from the server, by its AlertDescription number in RFC 8446.
+fn alert_error(alert: u8) -> TlsError {
+    match alert {
+        // handshake_failure, protocol_version, insufficient_security, inappropriate_fallback
+        40 | 70 | 71 | 86 => TlsError::VersionOrCipherMismatch,
+        // The server rejected our client certificate: no_certificate, bad_certificate,
+        // unsupported_certificate, certificate_revoked, certificate_expired, certificate_unknown,
+        // unknown_ca, access_denied, certificate_required
+        41..=46 | 48 | 49 | 116 => TlsError::BadClientAuthCert,
+        _ => TlsError::ProtocolError,
+    }
+}
+
+/// Finds a native-tls or OpenSSL error in the source chain of a connection error. If the only
+/// one found says no more than that the certificate is invalid, the rest of the chain is searched
+/// for something more precise before settling for it.
+#[cfg(all(
+    any(feature = "native-tls-error", feature = "openssl-error"),
+    any(feature = "reqwest-error", feature = "hyper1-error")
+))]
+fn tls_source(err: &(dyn std::error::Error + 'static)) -> Option<Error> {
+    let mut cert_invalid = None;
+    let mut source = err.source();
+    while let Some(err) = source {
+        let mut found: Option<Error> = None;
+        #[cfg(feature = "native-tls-error")]
+        if let Some(tls_err) = err.downcast_ref::<::native_tls::Error>() {
+            found = Some(tls_err.into());
+        }
+        #[cfg(all(
+            feature = "openssl-error",
+            not(any(target_os = "macos", target_os = "ios", windows))
+        ))]
+        {
+            if let Some(ssl_err) = err.downcast_ref::<::openssl::ssl::Error>() {
+                found = found.or_else(|| Some(ssl_err.into()));
+            }
+            if let Some(stack) = err.downcast_ref::<::openssl::error::ErrorStack>() {
+                found = found.or_else(|| Some(stack.into()));
+            }
+        }
+        match found {
+            Some(Error::Tls(TlsError::CertInvalid)) => {
+                cert_invalid = Some(Error::Tls(TlsError::CertInvalid))
+            }
+            Some(found) => return Some(found),
+            None => {}
+        }
+
+        source = err.source();
+    }
+    cert_invalid
+}
+
+/// Classifies an error of unknown type, by finding an error we know how to classify in it or its
+/// sources.
+#[cfg(feature = "tower")]
+pub(crate) fn from_dyn(err: &(dyn std::error::Error + 'static)) -> Error {
+    macro_rules! downcast {
+        ($err:expr, $($ty:ty),+) => {
+            $(
+                if let Some(err) = $err.downcast_ref::<$ty>() {
+                    return err.into();
                 }
-                _ => match err.get_ref() {
-                    None => Error::new("tcp", "failed"),
-                    Some(inner) => {
-                        if inner.downcast_ref::<rustls::Error>().is_some() {
-                            Error::new("tls", "protocol.error")
-                        } else {
-                            Error::new("unknown", err)
-                        }
-                    }
-                },
-            },
+            )+
+        };
+    }
+
+    let mut source = Some(err);
+    while let Some(err) = source {
+        #[cfg(feature = "hyper1-error")]
+        downcast!(err, ::hyper_util::client::legacy::Error, ::hyper1::Error);
+        #[cfg(feature = "reqwest-error")]
+        downcast!(err, ::reqwest::Error, ::hyper::Error);
+        #[cfg(feature = "reqwest012-error")]
+        downcast!(err, ::reqwest012::Error);
+        #[cfg(feature = "h2-error")]
+        downcast!(err, ::h2::Error, ::h2_04::Error);
+        #[cfg(feature = "quinn-error")]
+        downcast!(err, ::quinn::ConnectionError, ::quinn::ConnectError);
+        #[cfg(feature = "hickory-error")]
+        downcast!(err, ::hickory_resolver::error::ResolveError);
+        #[cfg(feature = "native-tls-error")]
+        downcast!(err, ::native_tls::Error);
+        #[cfg(all(
+            feature = "openssl-error",
+            not(any(target_os = "macos", target_os = "ios", windows))
+        ))]
+        downcast!(err, ::openssl::ssl::Error, ::openssl::error::ErrorStack);
+        downcast!(err, std::io::Error, ::rustls::Error);
+
+        source = err.source();
+    }
+    Error::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_type_round_trip() {
+        for error_type in [
+            "dns.address_changed",
+            "tcp.timed_out",
+            "udp.reset",
+            "tls.cert.pinned_key_not_in_cert_chain",
+            "http.response.invalid.content_length_mismatch",
+            "abandoned",
+            "unknown",
+        ] {
+            let err: Error = error_type.parse().unwrap();
+            assert_eq!(err.to_string(), error_type);
+        }
+
+        assert!("tcp.timeout".parse::<Error>().is_err());
+        assert!("abandoned.early".parse::<Error>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn io_error_codes() {
+        let cases = [
+            (libc::ECONNREFUSED, "tcp.refused"),
+            (libc::EHOSTUNREACH, "tcp.address_unreachable"),
+            (libc::ENETUNREACH, "tcp.address_unreachable"),
+            (libc::EADDRNOTAVAIL, "tcp.address_invalid"),
+            (libc::EPIPE, "tcp.closed"),
+        ];
+        for (code, error_type) in cases {
+            let err = std::io::Error::from_raw_os_error(code);
+            assert_eq!(Error::from(&err).to_string(), error_type);
         }
     }
+
+    #[test]
+    fn io_error_dns() {
+        let err = std::io::Error::other(
+            "failed to lookup address information: Temporary failure in name resolution",
+        );
+        assert_eq!(Error::from(&err), Error::Dns(DnsError::Unreachable));
+    }
+
+    #[test]
+    fn custom_error_type() {
+        let err = Error::custom(Phase::Application, "http.response.rejected_by_waf");
+        assert_eq!(err.to_string(), "http.response.rejected_by_waf");
+        assert_eq!(err.phase(), Phase::Application);
+    }
+
+    #[test]
+    fn phase_at_stage() {
+        let reset = Error::Tcp(TransportError::Reset);
+        assert_eq!(reset.phase_at(Stage::Connecting), Phase::Connection);
+        assert_eq!(reset.phase_at(Stage::AwaitingResponse), Phase::Application);
+        assert_eq!(
+            Error::Tls(TlsError::CertInvalid).phase_at(Stage::Resolving),
+            Phase::Connection
+        );
+        assert_eq!(Error::Abandoned.phase_at(Stage::Resolving), Phase::Dns);
+        assert_eq!(
+            Error::Dns(DnsError::NameNotResolved).phase_at(Stage::Connecting),
+            Phase::Dns
+        );
+        assert_eq!(
+            Error::custom(Phase::Dns, "dns.custom").phase_at(Stage::ReadingBody),
+            Phase::Dns
+        );
+    }
 }
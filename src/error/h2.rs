@@ -0,0 +1,130 @@
+use super::{Error, HttpError, TlsError, TransportError};
+
+// Error codes from RFC 9113, section 7.
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const SETTINGS_TIMEOUT: u32 = 0x4;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const CANCEL: u32 = 0x8;
+const COMPRESSION_ERROR: u32 = 0x9;
+const CONNECT_ERROR: u32 = 0xa;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+const INADEQUATE_SECURITY: u32 = 0xc;
+const HTTP_1_1_REQUIRED: u32 = 0xd;
+
+/// Maps an HTTP/2 error code from a RST_STREAM or GOAWAY frame. `remote` is whether the server
+/// sent the frame, rather than h2 sending it after noticing a problem itself.
+fn reason_error(reason: u32, remote: bool) -> Error {
+    match reason {
+        // The server shut the connection down gracefully before handling the request.
+        NO_ERROR => Error::Tcp(TransportError::Closed),
+
+        // If we found these ourselves, the server sent frames we couldn't make sense of.
+        PROTOCOL_ERROR | FRAME_SIZE_ERROR | COMPRESSION_ERROR if !remote => {
+            Error::Http(HttpError::ResponseInvalid)
+        }
+        PROTOCOL_ERROR | INTERNAL_ERROR | FLOW_CONTROL_ERROR | SETTINGS_TIMEOUT | STREAM_CLOSED
+        | FRAME_SIZE_ERROR | COMPRESSION_ERROR | HTTP_1_1_REQUIRED => {
+            Error::Http(HttpError::ProtocolError)
+        }
+
+        // The server dropped the stream without processing it.
+        REFUSED_STREAM | CONNECT_ERROR | ENHANCE_YOUR_CALM => Error::Tcp(TransportError::Reset),
+        CANCEL if remote => Error::Tcp(TransportError::Reset),
+        CANCEL => Error::Abandoned,
+
+        INADEQUATE_SECURITY => Error::Tls(TlsError::VersionOrCipherMismatch),
+
+        _ => Error::Http(HttpError::ProtocolError),
+    }
+}
+
+impl From<&h2::Error> for Error {
+    fn from(err: &h2::Error) -> Self {
+        if let Some(io_err) = err.get_io() {
+            return io_err.into();
+        }
+        match err.reason() {
+            Some(reason) => reason_error(reason.into(), err.is_remote()),
+            // h2 rejected something we tried to do.
+            None => Error::Http(HttpError::ProtocolError),
+        }
+    }
+}
+
+impl From<&h2_04::Error> for Error {
+    fn from(err: &h2_04::Error) -> Self {
+        if let Some(io_err) = err.get_io() {
+            return io_err.into();
+        }
+        match err.reason() {
+            Some(reason) => reason_error(reason.into(), err.is_remote()),
+            // h2 rejected something we tried to do.
+            None => Error::Http(HttpError::ProtocolError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use h2_04::Reason;
+
+    #[test]
+    fn reasons() {
+        let cases = [
+            (Reason::NO_ERROR, "tcp.closed"),
+            (Reason::PROTOCOL_ERROR, "http.response.invalid"),
+            (Reason::FLOW_CONTROL_ERROR, "http.protocol.error"),
+            (Reason::REFUSED_STREAM, "tcp.reset"),
+            (Reason::CANCEL, "abandoned"),
+            (Reason::ENHANCE_YOUR_CALM, "tcp.reset"),
+            (
+                Reason::INADEQUATE_SECURITY,
+                "tls.version_or_cipher_mismatch",
+            ),
+        ];
+        for (reason, error_type) in cases {
+            let err = h2_04::Error::from(reason);
+            assert_eq!(Error::from(&err).to_string(), error_type);
+        }
+
+        assert_eq!(
+            reason_error(CANCEL, true),
+            Error::Tcp(TransportError::Reset)
+        );
+        assert_eq!(
+            reason_error(PROTOCOL_ERROR, true),
+            Error::Http(HttpError::ProtocolError)
+        );
+    }
+
+    #[cfg(feature = "hyper1-error")]
+    #[tokio::test]
+    async fn refused_stream() {
+        use http_body_util::Empty;
+        use hyper_util::client::legacy::Client;
+        use hyper_util::rt::TokioExecutor;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = h2_04::server::handshake(stream).await.unwrap();
+            while let Some(Ok((_, mut respond))) = conn.accept().await {
+                respond.send_reset(Reason::REFUSED_STREAM);
+            }
+        });
+
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http::<Empty<bytes::Bytes>>();
+        let uri = format!("http://{}/", addr).parse().unwrap();
+        let err = client.get(uri).await.unwrap_err();
+        assert_eq!(Error::from(&err), Error::Tcp(TransportError::Reset));
+    }
+}
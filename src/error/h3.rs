@@ -0,0 +1,88 @@
+use super::quinn::application_error;
+use super::{Error, HttpError, TransportError};
+use h3::error::{ConnectionError, LocalError, StreamError};
+use h3::quic::ConnectionErrorIncoming;
+
+impl From<&ConnectionError> for Error {
+    fn from(err: &ConnectionError) -> Self {
+        match err {
+            ConnectionError::Local { error, .. } => match error {
+                LocalError::Application { code, .. } => application_error(code.value(), false),
+                // We closed the connection ourselves.
+                _ => Error::Abandoned,
+            },
+            ConnectionError::Remote { 0: err, .. } => err.into(),
+            ConnectionError::Timeout { .. } => Error::Udp(TransportError::TimedOut),
+            _ => Error::Unknown,
+        }
+    }
+}
+
+impl From<&ConnectionErrorIncoming> for Error {
+    fn from(err: &ConnectionErrorIncoming) -> Self {
+        match err {
+            ConnectionErrorIncoming::ApplicationClose { error_code } => {
+                application_error(*error_code, true)
+            }
+            ConnectionErrorIncoming::Timeout => Error::Udp(TransportError::TimedOut),
+            // The QUIC implementation's own error, which is where transport and TLS failures end
+            // up.
+            ConnectionErrorIncoming::Undefined(err) => {
+                match err.downcast_ref::<quinn::ConnectionError>() {
+                    Some(quinn_err) => quinn_err.into(),
+                    None => Error::Udp(TransportError::Failed),
+                }
+            }
+            ConnectionErrorIncoming::InternalError(_) => Error::Udp(TransportError::Failed),
+        }
+    }
+}
+
+impl From<&StreamError> for Error {
+    fn from(err: &StreamError) -> Self {
+        match err {
+            StreamError::StreamError { code, .. } => application_error(code.value(), false),
+            StreamError::RemoteTerminate { code, .. } => application_error(code.value(), true),
+            StreamError::ConnectionError { 0: err, .. } => err.into(),
+            // The request's headers are larger than the server allows.
+            StreamError::HeaderTooBig { .. } => Error::Http(HttpError::ProtocolError),
+            // The server sent GOAWAY before handling the request.
+            StreamError::RemoteClosing { .. } => Error::Udp(TransportError::Closed),
+            StreamError::Undefined { 0: err, .. } => {
+                match err.downcast_ref::<quinn::ConnectionError>() {
+                    Some(quinn_err) => quinn_err.into(),
+                    None => Error::Udp(TransportError::Failed),
+                }
+            }
+            _ => Error::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn incoming_errors() {
+        let cases = [
+            (
+                ConnectionErrorIncoming::ApplicationClose { error_code: 0x100 },
+                "udp.closed",
+            ),
+            (
+                ConnectionErrorIncoming::ApplicationClose { error_code: 0x10e },
+                "http.protocol.error",
+            ),
+            (ConnectionErrorIncoming::Timeout, "udp.timed_out"),
+            (
+                ConnectionErrorIncoming::Undefined(Arc::new(quinn::ConnectionError::Reset)),
+                "udp.reset",
+            ),
+        ];
+        for (err, error_type) in cases {
+            assert_eq!(Error::from(&err).to_string(), error_type);
+        }
+    }
+}
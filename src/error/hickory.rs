@@ -0,0 +1,78 @@
+use super::{DnsError, Error};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::error::ProtoErrorKind;
+use hickory_resolver::proto::op::ResponseCode;
+
+impl From<&ResolveError> for Error {
+    fn from(err: &ResolveError) -> Self {
+        let dns = match err.kind() {
+            // The name doesn't exist, or has no addresses.
+            ResolveErrorKind::NoRecordsFound {
+                response_code: ResponseCode::NXDomain | ResponseCode::NoError,
+                ..
+            } => DnsError::NameNotResolved,
+
+            // We couldn't get an answer from any name server.
+            ResolveErrorKind::Timeout
+            | ResolveErrorKind::NoConnections
+            | ResolveErrorKind::Io(_) => DnsError::Unreachable,
+            ResolveErrorKind::Proto(proto_err) => match proto_err.kind() {
+                ProtoErrorKind::Timeout | ProtoErrorKind::Busy | ProtoErrorKind::Io(_) => {
+                    DnsError::Unreachable
+                }
+                _ => DnsError::Failed,
+            },
+
+            _ => DnsError::Failed,
+        };
+        Error::Dns(dns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Phase;
+    use hickory_resolver::proto::op::Query;
+    use hickory_resolver::proto::rr::RecordType;
+    use hickory_resolver::Name;
+
+    fn no_records(response_code: ResponseCode) -> ResolveError {
+        ResolveErrorKind::NoRecordsFound {
+            query: Box::new(Query::query(
+                Name::from_ascii("invalid.").unwrap(),
+                RecordType::A,
+            )),
+            soa: None,
+            negative_ttl: None,
+            response_code,
+            trusted: true,
+        }
+        .into()
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let cases = [
+            (no_records(ResponseCode::NXDomain), "dns.name_not_resolved"),
+            (no_records(ResponseCode::ServFail), "dns.failed"),
+            (ResolveErrorKind::Timeout.into(), "dns.unreachable"),
+            (
+                std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into(),
+                "dns.unreachable",
+            ),
+            (ResolveError::from("bad response"), "dns.failed"),
+        ];
+        for (err, error_type) in cases {
+            let nel_err = Error::from(&err);
+            assert_eq!(nel_err.to_string(), error_type);
+            assert_eq!(nel_err.phase(), Phase::Dns);
+        }
+    }
+
+    #[test]
+    fn io_error_delegates() {
+        let err: std::io::Error = ResolveError::from(ResolveErrorKind::Timeout).into();
+        assert_eq!(Error::from(&err), Error::Dns(DnsError::Unreachable));
+    }
+}
@@ -0,0 +1,142 @@
+use super::{HttpError, TransportError};
+use hyper_util::client::legacy;
+
+impl From<&legacy::Error> for super::Error {
+    fn from(err: &legacy::Error) -> Self {
+        use std::error::Error;
+
+        if err.is_connect() {
+            // this was an error from the connector, which wraps the underlying cause.
+            #[cfg(any(feature = "native-tls-error", feature = "openssl-error"))]
+            if let Some(tls_err) = super::tls_source(err) {
+                return tls_err;
+            }
+
+            let mut source = err.source();
+            while let Some(err) = source {
+                if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                    return io_err.into();
+                }
+
+                source = err.source();
+            }
+
+            return super::Error::Tcp(TransportError::Failed);
+        }
+
+        // Otherwise the request failed on an established connection, so trace it to the
+        // underlying hyper::Error.
+        let mut source = err.source();
+        while let Some(err) = source {
+            if let Some(hyper_err) = err.downcast_ref::<hyper1::Error>() {
+                return hyper_err.into();
+            }
+
+            source = err.source();
+        }
+
+        super::Error::Unknown
+    }
+}
+
+impl From<&hyper1::Error> for super::Error {
+    fn from(err: &hyper1::Error) -> Self {
+        use std::error::Error;
+
+        // If this is caused by an underlying HTTP/2 or I/O error, delegate to that.
+        let mut source = err.source();
+        while let Some(err) = source {
+            #[cfg(feature = "h2-error")]
+            if let Some(h2_err) = err.downcast_ref::<h2_04::Error>() {
+                return h2_err.into();
+            }
+            if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                return io_err.into();
+            }
+
+            source = err.source();
+        }
+
+        if err.is_parse() {
+            // this was an HTTP parse error.
+            super::Error::Http(HttpError::ResponseInvalid)
+        } else if err.is_user() {
+            // this error was caused by user code.
+            super::Error::Http(HttpError::ProtocolError)
+        } else if err.is_incomplete_message() {
+            // the connection closed before a message could complete.
+            super::Error::Tcp(TransportError::Closed)
+        } else if err.is_body_write_aborted() {
+            // the body write was aborted.
+            super::Error::Abandoned
+        } else if err.is_timeout() {
+            super::Error::Tcp(TransportError::TimedOut)
+        } else if err.is_closed() {
+            // a sender's channel was closed.
+            super::Error::Tcp(TransportError::Reset)
+        } else if err.is_canceled() {
+            // the `Request` was canceled.
+            super::Error::Tcp(TransportError::Aborted)
+        } else {
+            super::Error::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Error, Phase};
+    use http_body_util::Empty;
+    use hyper_util::client::legacy::Client;
+    use hyper_util::rt::TokioExecutor;
+    use std::net::TcpListener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn get(uri: String) -> Error {
+        let client = Client::builder(TokioExecutor::new()).build_http::<Empty<bytes::Bytes>>();
+        let err = client.get(uri.parse().unwrap()).await.unwrap_err();
+        (&err).into()
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        // Bind and drop a listener to find a port that nothing is listening on.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = get(format!("http://{}/", addr)).await;
+        assert_eq!(err.to_string(), "tcp.refused");
+        assert_eq!(err.phase(), Phase::Connection);
+    }
+
+    #[tokio::test]
+    async fn invalid_response() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(b"not http\r\n\r\n").await;
+        });
+
+        let err = get(format!("http://{}/", addr)).await;
+        assert_eq!(err.to_string(), "http.response.invalid");
+        assert_eq!(err.phase(), Phase::Application);
+    }
+
+    #[tokio::test]
+    async fn incomplete_response() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await;
+        });
+
+        let err = get(format!("http://{}/", addr)).await;
+        assert_eq!(err.to_string(), "tcp.closed");
+    }
+}
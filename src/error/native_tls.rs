@@ -0,0 +1,201 @@
+use super::{Error, TlsError};
+
+impl From<&native_tls::Error> for Error {
+    fn from(err: &native_tls::Error) -> Self {
+        use std::error::Error;
+
+        if let Some(tls) = verify_result(err) {
+            return super::Error::Tls(tls);
+        }
+
+        // native-tls doesn't expose its backend's error, only the errors behind it. Where the
+        // backend is OpenSSL, those say why the handshake failed, though not which check a
+        // certificate failed.
+        let mut source = err.source();
+        while let Some(err) = source {
+            #[cfg(all(
+                feature = "openssl-error",
+                not(any(target_os = "macos", target_os = "ios", windows))
+            ))]
+            {
+                if let Some(ssl_err) = err.downcast_ref::<openssl::ssl::Error>() {
+                    return ssl_err.into();
+                }
+                if let Some(stack) = err.downcast_ref::<openssl::error::ErrorStack>() {
+                    return stack.into();
+                }
+            }
+            if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                return io_err.into();
+            }
+
+            source = err.source();
+        }
+
+        super::Error::Tls(TlsError::Failed)
+    }
+}
+
+/// Finds which check a certificate failed. native-tls doesn't expose the verification result,
+/// but where its backend is OpenSSL, the result's X509_V_ERR_* code is part of the error's Debug
+/// output.
+fn verify_result(err: &native_tls::Error) -> Option<TlsError> {
+    let debug = format!("{:?}", err);
+    let code = debug.split("X509VerifyResult { code: ").nth(1)?;
+    let end = code.find(|c: char| !c.is_ascii_digit())?;
+    match code[..end].parse::<i32>().ok()? {
+        // X509_V_ERR_CERT_NOT_YET_VALID, X509_V_ERR_CERT_HAS_EXPIRED
+        9 | 10 => Some(TlsError::CertDateInvalid),
+        // X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT, X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN,
+        // X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY, X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE
+        18..=21 => Some(TlsError::CertAuthorityInvalid),
+        // X509_V_ERR_HOSTNAME_MISMATCH
+        62 => Some(TlsError::CertNameInvalid),
+        _ => None,
+    }
+}
+
+impl<S> From<&native_tls::HandshakeError<S>> for Error {
+    fn from(err: &native_tls::HandshakeError<S>) -> Self {
+        match err {
+            native_tls::HandshakeError::Failure(err) => err.into(),
+            native_tls::HandshakeError::WouldBlock(_) => Error::Tls(TlsError::Failed),
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "openssl-error",
+    not(any(target_os = "macos", target_os = "ios", windows))
+))]
+mod tests {
+    use crate::error::Error;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread::JoinHandle;
+
+    /// Creates a self-signed certificate for `name`, optionally one that has already expired.
+    fn certificate(name: &str, expired: bool) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_issuer_name(&subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        if expired {
+            builder
+                .set_not_before(&Asn1Time::from_unix(0).unwrap())
+                .unwrap();
+            builder
+                .set_not_after(&Asn1Time::from_unix(86400).unwrap())
+                .unwrap();
+        } else {
+            builder
+                .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            builder
+                .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+        }
+        let san = SubjectAlternativeName::new()
+            .dns(name)
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (builder.build(), key)
+    }
+
+    /// Serves one TLS handshake with the certificate on a local port.
+    fn serve(cert: &X509, key: &PKey<Private>) -> (SocketAddr, JoinHandle<()>) {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(key).unwrap();
+        acceptor.set_certificate(cert).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = acceptor.accept(stream);
+        });
+        (addr, server)
+    }
+
+    /// Returns the error that native-tls reports when connecting to a server with the
+    /// certificate as `domain`.
+    fn handshake(cert: &X509, key: &PKey<Private>, domain: &str, trusted: bool) -> Error {
+        let (addr, server) = serve(cert, key);
+
+        let mut connector = native_tls::TlsConnector::builder();
+        if trusted {
+            let root = native_tls::Certificate::from_der(&cert.to_der().unwrap()).unwrap();
+            connector.add_root_certificate(root);
+        }
+        let connector = connector.build().unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let err = connector.connect(domain, stream).unwrap_err();
+        server.join().unwrap();
+
+        (&err).into()
+    }
+
+    #[test]
+    fn untrusted_cert() {
+        let (cert, key) = certificate("localhost", false);
+        let err = handshake(&cert, &key, "localhost", false);
+        assert_eq!(err.to_string(), "tls.cert.authority_invalid");
+    }
+
+    #[test]
+    fn expired_cert() {
+        let (cert, key) = certificate("localhost", true);
+        let err = handshake(&cert, &key, "localhost", true);
+        assert_eq!(err.to_string(), "tls.cert.date_invalid");
+    }
+
+    #[test]
+    fn invalid_name() {
+        let (cert, key) = certificate("localhost", false);
+        let err = handshake(&cert, &key, "wrong.localhost", true);
+        assert_eq!(err.to_string(), "tls.cert.name_invalid");
+    }
+
+    // The same failure, seen through hyper's connection error rather than on its own.
+    #[cfg(feature = "reqwest-error")]
+    #[tokio::test]
+    async fn expired_cert_through_reqwest() {
+        let (cert, key) = certificate("localhost", true);
+        let (addr, server) = serve(&cert, &key);
+
+        let root = reqwest::Certificate::from_der(&cert.to_der().unwrap()).unwrap();
+        let client = reqwest::ClientBuilder::new()
+            .use_native_tls()
+            .add_root_certificate(root)
+            .build()
+            .unwrap();
+        let err = client
+            .get(format!("https://localhost:{}/", addr.port()))
+            .send()
+            .await
+            .unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(Error::from(&err).to_string(), "tls.cert.date_invalid");
+    }
+}
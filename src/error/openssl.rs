@@ -0,0 +1,169 @@
+use super::{alert_error, Error, TlsError, TransportError};
+use openssl::error::ErrorStack;
+use openssl::ssl::{self, HandshakeError};
+use openssl::x509::X509VerifyResult;
+use openssl_sys as ffi;
+use std::convert::TryFrom;
+
+// Error library and reason codes from OpenSSL's sslerr.h, which openssl-sys doesn't export.
+const ERR_LIB_SSL: i32 = 20;
+const SSL_R_CERTIFICATE_VERIFY_FAILED: i32 = 134;
+const SSL_R_VERSION_TOO_HIGH: i32 = 166;
+const SSL_R_NO_CIPHERS_AVAILABLE: i32 = 181;
+const SSL_R_NO_PROTOCOLS_AVAILABLE: i32 = 191;
+const SSL_R_NO_SHARED_CIPHER: i32 = 193;
+const SSL_R_UNSUPPORTED_PROTOCOL: i32 = 258;
+const SSL_R_WRONG_VERSION_NUMBER: i32 = 267;
+const SSL_R_UNEXPECTED_EOF_WHILE_READING: i32 = 294;
+const SSL_R_VERSION_TOO_LOW: i32 = 396;
+/// Reasons at this offset and above are TLS alerts received from the peer.
+const SSL_AD_REASON_OFFSET: i32 = 1000;
+
+/// Certificate verification results that map to something more precise than tls.cert.invalid.
+const VERIFY_RESULTS: &[(i32, TlsError)] = &[
+    (
+        ffi::X509_V_ERR_CERT_NOT_YET_VALID,
+        TlsError::CertDateInvalid,
+    ),
+    (ffi::X509_V_ERR_CERT_HAS_EXPIRED, TlsError::CertDateInvalid),
+    (
+        ffi::X509_V_ERR_ERROR_IN_CERT_NOT_BEFORE_FIELD,
+        TlsError::CertDateInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_ERROR_IN_CERT_NOT_AFTER_FIELD,
+        TlsError::CertDateInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (
+        ffi::X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (ffi::X509_V_ERR_INVALID_CA, TlsError::CertAuthorityInvalid),
+    (
+        ffi::X509_V_ERR_CERT_UNTRUSTED,
+        TlsError::CertAuthorityInvalid,
+    ),
+    (ffi::X509_V_ERR_HOSTNAME_MISMATCH, TlsError::CertNameInvalid),
+    (ffi::X509_V_ERR_EMAIL_MISMATCH, TlsError::CertNameInvalid),
+    (
+        ffi::X509_V_ERR_IP_ADDRESS_MISMATCH,
+        TlsError::CertNameInvalid,
+    ),
+    (ffi::X509_V_ERR_CERT_REVOKED, TlsError::CertRevoked),
+];
+
+impl From<X509VerifyResult> for Error {
+    fn from(result: X509VerifyResult) -> Self {
+        let code = result.as_raw();
+        let tls = VERIFY_RESULTS
+            .iter()
+            .find(|(verify_code, _)| *verify_code == code)
+            .map(|(_, tls)| *tls)
+            .unwrap_or(TlsError::CertInvalid);
+        Error::Tls(tls)
+    }
+}
+
+impl From<&ErrorStack> for Error {
+    fn from(stack: &ErrorStack) -> Self {
+        for err in stack.errors() {
+            if err.library_code() != ERR_LIB_SSL {
+                continue;
+            }
+            let tls = match err.reason_code() {
+                // The verification result isn't part of the error stack.
+                SSL_R_CERTIFICATE_VERIFY_FAILED => TlsError::CertInvalid,
+                SSL_R_VERSION_TOO_HIGH
+                | SSL_R_VERSION_TOO_LOW
+                | SSL_R_NO_CIPHERS_AVAILABLE
+                | SSL_R_NO_PROTOCOLS_AVAILABLE
+                | SSL_R_NO_SHARED_CIPHER
+                | SSL_R_UNSUPPORTED_PROTOCOL => TlsError::VersionOrCipherMismatch,
+                SSL_R_WRONG_VERSION_NUMBER => TlsError::ProtocolError,
+                SSL_R_UNEXPECTED_EOF_WHILE_READING => return Error::Tcp(TransportError::Closed),
+                // OpenSSL reports an alert from the server as a reason code of its own.
+                reason if reason >= SSL_AD_REASON_OFFSET => {
+                    u8::try_from(reason - SSL_AD_REASON_OFFSET)
+                        .map_or(TlsError::ProtocolError, alert_error)
+                }
+                _ => continue,
+            };
+            return Error::Tls(tls);
+        }
+        Error::Tls(TlsError::Failed)
+    }
+}
+
+impl From<&ssl::Error> for Error {
+    fn from(err: &ssl::Error) -> Self {
+        if let Some(io_err) = err.io_error() {
+            return io_err.into();
+        }
+        if let Some(stack) = err.ssl_error() {
+            return stack.into();
+        }
+        match err.code() {
+            // The connection was closed, cleanly or otherwise.
+            ssl::ErrorCode::ZERO_RETURN | ssl::ErrorCode::SYSCALL => {
+                Error::Tcp(TransportError::Closed)
+            }
+            _ => Error::Tls(TlsError::Failed),
+        }
+    }
+}
+
+impl<S> From<&HandshakeError<S>> for Error {
+    fn from(err: &HandshakeError<S>) -> Self {
+        match err {
+            HandshakeError::SetupFailure(stack) => stack.into(),
+            HandshakeError::Failure(mid) | HandshakeError::WouldBlock(mid) => {
+                let result = mid.ssl().verify_result();
+                if result != X509VerifyResult::OK {
+                    result.into()
+                } else {
+                    mid.error().into()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_results() {
+        let cases = [
+            (ffi::X509_V_ERR_CERT_HAS_EXPIRED, "tls.cert.date_invalid"),
+            (
+                ffi::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT,
+                "tls.cert.authority_invalid",
+            ),
+            (ffi::X509_V_ERR_HOSTNAME_MISMATCH, "tls.cert.name_invalid"),
+            (ffi::X509_V_ERR_CERT_REVOKED, "tls.cert.revoked"),
+            (ffi::X509_V_ERR_INVALID_PURPOSE, "tls.cert.invalid"),
+        ];
+        for (code, error_type) in cases {
+            // Safety: the code is one of OpenSSL's X509_V_ERR_* constants.
+            let result = unsafe { X509VerifyResult::from_raw(code) };
+            assert_eq!(Error::from(result).to_string(), error_type);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use super::{alert_error, Error, HttpError, TlsError, TransportError};
+
+// Transport error codes from RFC 9000, section 20.1.
+const NO_ERROR: u64 = 0x0;
+const CONNECTION_REFUSED: u64 = 0x2;
+const NO_VIABLE_PATH: u64 = 0x10;
+const CRYPTO_ERROR: std::ops::Range<u64> = 0x100..0x200;
+
+// HTTP/3 error codes from RFC 9114, section 8.1, and QPACK's from RFC 9204, section 6.
+const H3_NO_ERROR: u64 = 0x100;
+const H3_GENERAL_PROTOCOL_ERROR: u64 = 0x101;
+const H3_FRAME_UNEXPECTED: u64 = 0x105;
+const H3_FRAME_ERROR: u64 = 0x106;
+const H3_EXCESSIVE_LOAD: u64 = 0x107;
+const H3_ID_ERROR: u64 = 0x108;
+const H3_SETTINGS_ERROR: u64 = 0x109;
+const H3_MISSING_SETTINGS: u64 = 0x10a;
+const H3_REQUEST_REJECTED: u64 = 0x10b;
+const H3_REQUEST_CANCELLED: u64 = 0x10c;
+const H3_MESSAGE_ERROR: u64 = 0x10e;
+const H3_CONNECT_ERROR: u64 = 0x10f;
+const H3_VERSION_FALLBACK: u64 = 0x110;
+const QPACK_DECOMPRESSION_FAILED: u64 = 0x200;
+const QPACK_ENCODER_STREAM_ERROR: u64 = 0x201;
+const QPACK_DECODER_STREAM_ERROR: u64 = 0x202;
+
+/// Maps a QUIC transport error code. `remote` is whether the server closed the connection with
+/// it, rather than our QUIC stack closing it after noticing a problem itself.
+fn transport_code_error(code: u64, remote: bool) -> Error {
+    match code {
+        NO_ERROR => Error::Udp(TransportError::Closed),
+        CONNECTION_REFUSED => Error::Udp(TransportError::Refused),
+        NO_VIABLE_PATH => Error::Udp(TransportError::AddressUnreachable),
+        // QUIC carries TLS alerts in the low byte of a CRYPTO_ERROR code.
+        code if CRYPTO_ERROR.contains(&code) => {
+            let alert = (code & 0xff) as u8;
+            if remote {
+                Error::Tls(alert_error(alert))
+            } else {
+                Error::Tls(local_alert_error(alert))
+            }
+        }
+        _ => Error::Udp(TransportError::Failed),
+    }
+}
+
+/// Maps a TLS alert that we sent, which for certificate alerts says what was wrong with the
+/// server's certificate.
+fn local_alert_error(alert: u8) -> TlsError {
+    match alert {
+        // handshake_failure, protocol_version, insufficient_security
+        40 | 70 | 71 => TlsError::VersionOrCipherMismatch,
+        // bad_certificate, unsupported_certificate, certificate_unknown
+        42 | 43 | 46 => TlsError::CertInvalid,
+        44 => TlsError::CertRevoked,
+        45 => TlsError::CertDateInvalid,
+        48 => TlsError::CertAuthorityInvalid,
+        _ => TlsError::ProtocolError,
+    }
+}
+
+/// Maps an HTTP/3 error code from a connection close or stream reset. `remote` is whether the
+/// server sent it, rather than our HTTP/3 stack sending it after noticing a problem itself.
+pub(super) fn application_error(code: u64, remote: bool) -> Error {
+    match code {
+        // The connection was closed without an error, while the request was still waiting on it.
+        H3_NO_ERROR => Error::Udp(TransportError::Closed),
+
+        // Framing, SETTINGS and QPACK errors that our HTTP/3 stack raised mean the response was
+        // malformed. When the server raises them, they're about our request.
+        H3_GENERAL_PROTOCOL_ERROR
+        | H3_FRAME_UNEXPECTED
+        | H3_FRAME_ERROR
+        | H3_ID_ERROR
+        | H3_SETTINGS_ERROR
+        | H3_MISSING_SETTINGS
+        | H3_MESSAGE_ERROR
+        | QPACK_DECOMPRESSION_FAILED
+        | QPACK_ENCODER_STREAM_ERROR
+        | QPACK_DECODER_STREAM_ERROR
+            if !remote =>
+        {
+            Error::Http(HttpError::ResponseInvalid)
+        }
+
+        // Codes that tell a client the request wasn't processed and can be retried.
+        H3_EXCESSIVE_LOAD | H3_REQUEST_REJECTED | H3_CONNECT_ERROR | H3_VERSION_FALLBACK => {
+            Error::Udp(TransportError::Reset)
+        }
+        H3_REQUEST_CANCELLED if remote => Error::Udp(TransportError::Reset),
+        H3_REQUEST_CANCELLED => Error::Abandoned,
+
+        // Everything else, such as H3_INTERNAL_ERROR or the server finding our request malformed,
+        // is a failure of the HTTP/3 exchange.
+        _ => Error::Http(HttpError::ProtocolError),
+    }
+}
+
+impl From<&quinn::ConnectionError> for Error {
+    fn from(err: &quinn::ConnectionError) -> Self {
+        match err {
+            quinn::ConnectionError::TransportError(err) => {
+                transport_code_error(err.code.into(), false)
+            }
+            quinn::ConnectionError::ConnectionClosed(close) => {
+                transport_code_error(close.error_code.into(), true)
+            }
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                application_error(close.error_code.into(), true)
+            }
+            quinn::ConnectionError::Reset => Error::Udp(TransportError::Reset),
+            quinn::ConnectionError::TimedOut => Error::Udp(TransportError::TimedOut),
+            quinn::ConnectionError::LocallyClosed => Error::Abandoned,
+            // There's no more precise type for a failed QUIC version negotiation.
+            quinn::ConnectionError::VersionMismatch | quinn::ConnectionError::CidsExhausted => {
+                Error::Udp(TransportError::Failed)
+            }
+        }
+    }
+}
+
+impl From<&quinn::ConnectError> for Error {
+    fn from(err: &quinn::ConnectError) -> Self {
+        match err {
+            quinn::ConnectError::InvalidRemoteAddress(_)
+            | quinn::ConnectError::InvalidServerName(_) => {
+                Error::Udp(TransportError::AddressInvalid)
+            }
+            _ => Error::Udp(TransportError::Failed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quinn::{ApplicationClose, ConnectionClose, TransportErrorCode, VarInt};
+
+    #[test]
+    fn connection_errors() {
+        let closed = |error_code| {
+            quinn::ConnectionError::ConnectionClosed(ConnectionClose {
+                error_code,
+                frame_type: None,
+                reason: Default::default(),
+            })
+        };
+        let cases = [
+            (quinn::ConnectionError::TimedOut, "udp.timed_out"),
+            (quinn::ConnectionError::Reset, "udp.reset"),
+            (
+                closed(TransportErrorCode::CONNECTION_REFUSED),
+                "udp.refused",
+            ),
+            (
+                closed(TransportErrorCode::crypto(116)),
+                "tls.bad_client_auth_cert",
+            ),
+            (
+                quinn::ConnectionError::ApplicationClosed(ApplicationClose {
+                    error_code: VarInt::from_u32(0x10b),
+                    reason: Default::default(),
+                }),
+                "udp.reset",
+            ),
+        ];
+        for (err, error_type) in cases {
+            assert_eq!(Error::from(&err).to_string(), error_type);
+        }
+
+        let err = quinn::ConnectError::InvalidServerName("bad name".into());
+        assert_eq!(Error::from(&err).to_string(), "udp.address_invalid");
+    }
+
+    #[test]
+    fn local_errors() {
+        assert_eq!(
+            transport_code_error(0x100 | 45, false),
+            Error::Tls(TlsError::CertDateInvalid)
+        );
+        assert_eq!(
+            transport_code_error(0x100 | 48, false),
+            Error::Tls(TlsError::CertAuthorityInvalid)
+        );
+        assert_eq!(
+            application_error(H3_FRAME_ERROR, false),
+            Error::Http(HttpError::ResponseInvalid)
+        );
+        assert_eq!(
+            application_error(H3_REQUEST_CANCELLED, false),
+            Error::Abandoned
+        );
+    }
+
+    #[test]
+    fn h3_reports() {
+        let mut report = crate::NELReport::new("https://example.com/".to_string());
+        report.set_h3_error(&quinn::ConnectionError::TimedOut);
+        assert_eq!(report.protocol, "h3");
+        assert_eq!(report.error(), Some(&Error::Udp(TransportError::TimedOut)));
+
+        // Transport errors from the OS are rewritten, since HTTP/3 runs over UDP.
+        let err = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        report.set_h3_error(&err);
+        assert_eq!(report.error(), Some(&Error::Udp(TransportError::Refused)));
+    }
+}
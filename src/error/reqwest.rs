@@ -1,7 +1,15 @@
+use super::{HttpError, TransportError};
+
 impl From<&reqwest::Error> for super::Error {
     fn from(err: &reqwest::Error) -> Self {
         use std::error::Error;
 
+        // A timeout set on the client or the request is reqwest's own, with no hyper error
+        // behind it.
+        if err.is_timeout() {
+            return super::Error::Tcp(TransportError::TimedOut);
+        }
+
         // First, attempt to trace this to an underlying hyper::Error
         let mut source = err.source();
         while let Some(err) = source {
@@ -12,7 +20,12 @@ impl From<&reqwest::Error> for super::Error {
             source = err.source();
         }
 
-        super::Error::new("unknown", err)
+        if err.is_redirect() {
+            // the redirect policy stopped following redirects.
+            super::Error::Http(HttpError::ResponseRedirectLoop)
+        } else {
+            super::Error::Unknown
+        }
     }
 }
 
@@ -20,9 +33,13 @@ impl From<&hyper::Error> for super::Error {
     fn from(err: &hyper::Error) -> Self {
         use std::error::Error;
 
-        // If this is caused by an underlying I/O error, delegate to that.
+        // If this is caused by an underlying HTTP/2 or I/O error, delegate to that.
         let mut source = err.source();
         while let Some(err) = source {
+            #[cfg(feature = "h2-error")]
+            if let Some(h2_err) = err.downcast_ref::<h2::Error>() {
+                return h2_err.into();
+            }
             if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
                 return io_err.into();
             }
@@ -34,50 +51,42 @@ impl From<&hyper::Error> for super::Error {
         // to handle them specially.
         if err.is_connect() {
             // this was an error from `Connect`. Could be any number of things.
-            if let Some(source) = err.source() {
-                match source.to_string() {
-                    s if s.contains("Hostname mismatch") => {
-                        super::Error::new("tls", "cert.name_invalid")
-                    }
-                    s if s.contains("certificate has expired") => {
-                        super::Error::new("tls", "cert.date_invalid")
-                    }
-                    s if s.contains("self signed certificate in certificate chain") => {
-                        super::Error::new("tls", "cert.authority_invalid")
-                    }
-                    _ => super::Error::new("tcp", "failed"),
-                }
-            } else {
-                super::Error::new("tcp", "failed")
+            #[cfg(any(feature = "native-tls-error", feature = "openssl-error"))]
+            if let Some(tls_err) = super::tls_source(err) {
+                return tls_err;
             }
+
+            super::Error::Tcp(TransportError::Failed)
         } else if err.is_parse() {
             // this was an HTTP parse error.
-            super::Error::new("http", "response.invalid")
+            super::Error::Http(HttpError::ResponseInvalid)
         } else if err.is_user() {
             // this error was caused by user code.
-            super::Error::new("http", "protocol.error")
+            super::Error::Http(HttpError::ProtocolError)
         } else if err.is_incomplete_message() {
             // the connection closed before a message could complete.
-            super::Error::new("tcp", "closed")
+            super::Error::Tcp(TransportError::Closed)
         } else if err.is_body_write_aborted() {
             // the body write was aborted.
-            super::Error::new("abandoned", "")
+            super::Error::Abandoned
         } else if err.is_timeout() {
-            super::Error::new("tcp", "timed_out")
+            super::Error::Tcp(TransportError::TimedOut)
         } else if err.is_closed() {
             // a sender's channel was closed.
-            super::Error::new("tcp", "reset")
+            super::Error::Tcp(TransportError::Reset)
         } else if err.is_canceled() {
             // the `Request` was canceled.
-            super::Error::new("tcp", "aborted")
+            super::Error::Tcp(TransportError::Aborted)
         } else {
-            super::Error::new("unknown", err)
+            super::Error::Unknown
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::Phase;
+
     // We can't get all of them, but at least here are the most common DNS and TLS failures.
     // We test them with both rustls and native-tls, because the errors are completely disjoint.
 
@@ -100,7 +109,7 @@ mod tests {
             let response = client.get("http://invalid.").send().await.unwrap_err();
             let nel_err: crate::error::Error = (&response).into();
             assert_eq!(nel_err.to_string(), "dns.name_not_resolved");
-            assert_eq!(nel_err.phase(), "dns");
+            assert_eq!(nel_err.phase(), Phase::Dns);
         }
     }
 
@@ -114,7 +123,7 @@ mod tests {
                 .unwrap_err();
             let nel_err: crate::error::Error = (&response).into();
             assert_eq!(nel_err.to_string(), "tls.cert.date_invalid");
-            assert_eq!(nel_err.phase(), "connection");
+            assert_eq!(nel_err.phase(), Phase::Connection);
         }
     }
 
@@ -128,7 +137,7 @@ mod tests {
                 .unwrap_err();
             let nel_err: crate::error::Error = (&response).into();
             assert_eq!(nel_err.to_string(), "tls.cert.authority_invalid");
-            assert_eq!(nel_err.phase(), "connection");
+            assert_eq!(nel_err.phase(), Phase::Connection);
         }
     }
 
@@ -142,7 +151,7 @@ mod tests {
                 .unwrap_err();
             let nel_err: crate::error::Error = (&response).into();
             assert_eq!(nel_err.to_string(), "tls.cert.name_invalid");
-            assert_eq!(nel_err.phase(), "connection");
+            assert_eq!(nel_err.phase(), Phase::Connection);
         }
     }
 }
@@ -0,0 +1,123 @@
+use super::{HttpError, TransportError};
+use hyper_util::client::legacy;
+
+impl From<&reqwest012::Error> for super::Error {
+    fn from(err: &reqwest012::Error) -> Self {
+        use std::error::Error;
+
+        // reqwest's own timeouts don't carry a hyper error, and a connect timeout would
+        // otherwise look like a generic connection failure.
+        if err.is_timeout() {
+            return super::Error::Tcp(TransportError::TimedOut);
+        }
+
+        // Attempt to trace this to an underlying hyper-util or hyper error.
+        let mut source = err.source();
+        while let Some(err) = source {
+            if let Some(client_err) = err.downcast_ref::<legacy::Error>() {
+                return client_err.into();
+            }
+            if let Some(hyper_err) = err.downcast_ref::<hyper1::Error>() {
+                return hyper_err.into();
+            }
+
+            source = err.source();
+        }
+
+        // Otherwise, fall back to how reqwest classifies it.
+        if err.is_connect() {
+            super::Error::Tcp(TransportError::Failed)
+        } else if err.is_redirect() {
+            // the redirect policy gave up, most likely because of a loop.
+            super::Error::Http(HttpError::ResponseRedirectLoop)
+        } else if err.is_decode() {
+            // the response body couldn't be decoded.
+            super::Error::Http(HttpError::ResponseInvalid)
+        } else if err.is_body() {
+            // the request or response body failed.
+            super::Error::Http(HttpError::Failed)
+        } else {
+            super::Error::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Error, Phase};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serves every connection by reading the request and writing `response`.
+    async fn serve(response: &'static [u8]) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(response).await;
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        // Bind and drop a listener to find a port that nothing is listening on.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = reqwest012::get(format!("http://{}/", addr))
+            .await
+            .unwrap_err();
+        let nel_err: Error = (&err).into();
+        assert_eq!(nel_err.to_string(), "tcp.refused");
+        assert_eq!(nel_err.phase(), Phase::Connection);
+    }
+
+    #[tokio::test]
+    async fn timed_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Accept the connection but never respond.
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let client = reqwest012::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let err = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap_err();
+        let nel_err: Error = (&err).into();
+        assert_eq!(nel_err.to_string(), "tcp.timed_out");
+    }
+
+    #[tokio::test]
+    async fn redirect_loop() {
+        let uri = serve(
+            b"HTTP/1.1 302 Found\r\nLocation: /\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let err = reqwest012::get(uri).await.unwrap_err();
+        let nel_err: Error = (&err).into();
+        assert_eq!(nel_err.to_string(), "http.response.redirect_loop");
+        assert_eq!(nel_err.phase(), Phase::Application);
+    }
+
+    #[tokio::test]
+    async fn invalid_response() {
+        let uri = serve(b"not http\r\n\r\n").await;
+        let err = reqwest012::get(uri).await.unwrap_err();
+        let nel_err: Error = (&err).into();
+        assert_eq!(nel_err.to_string(), "http.response.invalid");
+    }
+}
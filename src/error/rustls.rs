@@ -0,0 +1,89 @@
+use super::{alert_error, Error, TlsError};
+use rustls::CertificateError;
+
+impl From<&rustls::Error> for Error {
+    fn from(err: &rustls::Error) -> Self {
+        let tls = match err {
+            rustls::Error::InvalidCertificate(cert_err) => cert_error(cert_err),
+            rustls::Error::NoCertificatesPresented | rustls::Error::InvalidSct(_) => {
+                TlsError::CertInvalid
+            }
+
+            // We and the server couldn't agree on a protocol version or cipher suite.
+            rustls::Error::PeerIncompatible(_) => TlsError::VersionOrCipherMismatch,
+
+            // The server aborted the handshake with an alert.
+            rustls::Error::AlertReceived(alert) => alert_error(alert.get_u8()),
+
+            rustls::Error::InappropriateMessage { .. }
+            | rustls::Error::InappropriateHandshakeMessage { .. }
+            | rustls::Error::InvalidMessage(_)
+            | rustls::Error::DecryptError
+            | rustls::Error::PeerMisbehaved(_)
+            | rustls::Error::PeerSentOversizedRecord
+            | rustls::Error::NoApplicationProtocol => TlsError::ProtocolError,
+
+            _ => TlsError::Failed,
+        };
+        Error::Tls(tls)
+    }
+}
+
+fn cert_error(err: &CertificateError) -> TlsError {
+    match err {
+        CertificateError::Expired | CertificateError::NotValidYet => TlsError::CertDateInvalid,
+        CertificateError::UnknownIssuer => TlsError::CertAuthorityInvalid,
+        CertificateError::NotValidForName => TlsError::CertNameInvalid,
+        CertificateError::Revoked => TlsError::CertRevoked,
+        _ => TlsError::CertInvalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{AlertDescription, PeerIncompatible};
+
+    #[test]
+    fn rustls_errors() {
+        let cases = [
+            (
+                rustls::Error::InvalidCertificate(CertificateError::Expired),
+                "tls.cert.date_invalid",
+            ),
+            (
+                rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer),
+                "tls.cert.authority_invalid",
+            ),
+            (
+                rustls::Error::InvalidCertificate(CertificateError::NotValidForName),
+                "tls.cert.name_invalid",
+            ),
+            (
+                rustls::Error::InvalidCertificate(CertificateError::BadSignature),
+                "tls.cert.invalid",
+            ),
+            (
+                rustls::Error::PeerIncompatible(PeerIncompatible::NoCipherSuitesInCommon),
+                "tls.version_or_cipher_mismatch",
+            ),
+            (
+                rustls::Error::AlertReceived(AlertDescription::CertificateRequired),
+                "tls.bad_client_auth_cert",
+            ),
+            (rustls::Error::DecryptError, "tls.protocol.error"),
+        ];
+        for (err, error_type) in cases {
+            assert_eq!(Error::from(&err).to_string(), error_type);
+        }
+    }
+
+    #[test]
+    fn io_error_delegates() {
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::InvalidCertificate(CertificateError::Expired),
+        );
+        assert_eq!(Error::from(&err), Error::Tls(TlsError::CertDateInvalid));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::report::ReportFormat;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Instant;
+
+/// Body is the type-specific part of a Reporting API report.
+pub trait Body {
+    /// Returns the report type, such as "deprecation".
+    fn report_type(&self) -> &str;
+
+    /// Returns the endpoint group that reports with this body are sent to, unless the report
+    /// names another one.
+    fn group(&self) -> &str {
+        "default"
+    }
+
+    /// Returns the body as it's serialized in the report.
+    fn to_json(&self) -> Value;
+}
+
+/// Implements Body for a built-in body type, which is serialized as it is.
+macro_rules! builtin_body {
+    ($body:ty, $report_type:expr) => {
+        impl Body for $body {
+            fn report_type(&self) -> &str {
+                $report_type
+            }
+
+            fn to_json(&self) -> Value {
+                serde_json::to_value(self).unwrap()
+            }
+        }
+    };
+}
+
+/// DeprecationBody reports the use of a feature that will be removed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeprecationBody {
+    pub id: String,
+    /// When the feature will be removed, as an ISO 8601 date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anticipated_removal: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_number: Option<u32>,
+}
+
+builtin_body!(DeprecationBody, "deprecation");
+
+/// InterventionBody reports a request that was denied or changed for security, performance or
+/// user annoyance reasons.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterventionBody {
+    pub id: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_number: Option<u32>,
+}
+
+builtin_body!(InterventionBody, "intervention");
+
+/// CspViolationBody reports a violation of a Content Security Policy. The policy's report-to
+/// directive names the group the report should be sent to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CspViolationBody {
+    #[serde(rename = "documentURL")]
+    pub document_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer: Option<String>,
+    #[serde(rename = "blockedURL", skip_serializing_if = "Option::is_none")]
+    pub blocked_url: Option<String>,
+    pub effective_directive: String,
+    pub original_policy: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+    /// Either "enforce" or "report".
+    pub disposition: String,
+    pub status_code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_number: Option<u32>,
+}
+
+builtin_body!(CspViolationBody, "csp-violation");
+
+/// CrashBody reports that a process crashed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashBody {
+    /// Why the process crashed, such as "oom" or "unresponsive", if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+builtin_body!(CrashBody, "crash");
+
+/// CustomBody is a body for a report type that isn't built in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomBody {
+    pub report_type: String,
+    pub body: Value,
+}
+
+impl Body for CustomBody {
+    fn report_type(&self) -> &str {
+        &self.report_type
+    }
+
+    fn to_json(&self) -> Value {
+        self.body.clone()
+    }
+}
+
+/// Report is a report of a type other than network-error. It's sent to a named endpoint group
+/// of the origin it's about, with the same endpoint selection and retries as NEL reports, but
+/// without needing a NEL policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    captured: Instant,
+    report_type: String,
+    body: Value,
+
+    pub url: String,
+    /// The endpoint group the report is sent to.
+    pub group: String,
+
+    /// Overrides the URL host for the purpose of choosing where to submit the report.
+    pub host_override: Option<String>,
+}
+
+impl Report {
+    pub fn new<B: Body>(url: String, body: &B) -> Self {
+        Report {
+            captured: Instant::now(),
+            report_type: body.report_type().to_string(),
+            body: body.to_json(),
+            url,
+            group: body.group().to_string(),
+            host_override: None,
+        }
+    }
+
+    /// Returns the report type, such as "deprecation".
+    pub fn report_type(&self) -> &str {
+        &self.report_type
+    }
+
+    /// Returns the serialized body.
+    pub fn body(&self) -> &Value {
+        &self.body
+    }
+
+    /// Serializes the report in the given format.
+    pub(crate) fn serialize_as(&self, format: ReportFormat, user_agent: Option<&str>) -> String {
+        let hdr = GenericReportHeader {
+            age: self.captured.elapsed().as_millis() as usize,
+            report_type: &self.report_type,
+            url: &self.url,
+            user_agent: match format {
+                ReportFormat::Legacy => None,
+                ReportFormat::V1 => Some(user_agent.unwrap_or("")),
+            },
+            body: &self.body,
+        };
+        serde_json::to_string(&vec![hdr]).unwrap()
+    }
+}
+
+/// GenericReportHeader is the structure a Report is serialized as.
+#[derive(Serialize)]
+struct GenericReportHeader<'a> {
+    age: usize,
+    #[serde(rename = "type")]
+    report_type: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<&'a str>,
+    body: &'a Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serializes_bodies() {
+        let body = DeprecationBody {
+            id: "websql".to_string(),
+            message: "WebSQL is deprecated".to_string(),
+            line_number: Some(12),
+            ..Default::default()
+        };
+        let report = Report::new("https://example.com/".to_string(), &body);
+        assert_eq!(report.group, "default");
+
+        let json: Value =
+            serde_json::from_str(&report.serialize_as(ReportFormat::V1, Some("nel-test"))).unwrap();
+        assert_eq!(json[0]["type"], "deprecation");
+        assert_eq!(json[0]["user_agent"], "nel-test");
+        assert_eq!(
+            json[0]["body"],
+            json!({"id": "websql", "message": "WebSQL is deprecated", "lineNumber": 12})
+        );
+
+        let body = CspViolationBody {
+            document_url: "https://example.com/".to_string(),
+            blocked_url: Some("https://evil.example/x.js".to_string()),
+            ..Default::default()
+        };
+        let json = body.to_json();
+        assert_eq!(json["documentURL"], "https://example.com/");
+        assert_eq!(json["blockedURL"], "https://evil.example/x.js");
+
+        let body = CustomBody {
+            report_type: "deploy".to_string(),
+            body: json!({"version": "1.2.3"}),
+        };
+        let report = Report::new("https://example.com/".to_string(), &body);
+        let json: Value =
+            serde_json::from_str(&report.serialize_as(ReportFormat::Legacy, Some("nel-test")))
+                .unwrap();
+        assert_eq!(json[0]["type"], "deploy");
+        assert_eq!(json[0].get("user_agent"), None);
+        assert_eq!(json[0]["body"]["version"], "1.2.3");
+    }
+}
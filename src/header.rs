@@ -0,0 +1,533 @@
+use serde::{Deserialize, Serialize};
+
+/// NelHeader is the JSON value of a NEL header.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct NelHeader {
+    /// Name of group to send reports to.
+    pub(crate) report_to: String,
+    /// Lifetime of policy in seconds.
+    pub(crate) max_age: u64,
+    #[serde(default)]
+    pub(crate) include_subdomains: bool,
+    #[serde(default)]
+    pub(crate) success_fraction: f32,
+    #[serde(default = "default_failure_fraction")]
+    pub(crate) failure_fraction: f32,
+    /// Names of request headers whose values should be included in reports.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) request_headers: Vec<String>,
+    /// Names of response headers whose values should be included in reports.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) response_headers: Vec<String>,
+}
+
+const fn default_failure_fraction() -> f32 {
+    1.0
+}
+
+impl NelHeader {
+    pub(crate) fn validate(&self) -> Result<(), HeaderError> {
+        if self.report_to.is_empty() {
+            return Err(HeaderError::EmptyGroup);
+        }
+        for fraction in [self.success_fraction, self.failure_fraction] {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(HeaderError::InvalidFraction(fraction));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ReportToHeader is the JSON value of a Report-To header.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ReportToHeader {
+    /// Name of this group of endpoints.
+    pub(crate) group: String,
+    /// Lifetime of policy in seconds.
+    pub(crate) max_age: u64,
+    pub(crate) endpoints: Vec<ReportEndpoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ReportEndpoint {
+    pub(crate) url: String,
+}
+
+impl ReportToHeader {
+    pub(crate) fn validate(&self) -> Result<(), HeaderError> {
+        if self.group.is_empty() {
+            return Err(HeaderError::EmptyGroup);
+        }
+        if self.endpoints.is_empty() {
+            return Err(HeaderError::NoEndpoints);
+        }
+        if self.endpoints.iter().any(|ep| ep.url.is_empty()) {
+            return Err(HeaderError::InvalidEndpoint(String::new()));
+        }
+        Ok(())
+    }
+}
+
+/// HeaderError describes why a policy header couldn't be built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    /// The group name is empty.
+    EmptyGroup,
+    /// A sampling fraction is outside of 0..=1.
+    InvalidFraction(f32),
+    /// The group has no endpoints.
+    NoEndpoints,
+    /// An endpoint URL is empty, or can't be represented in the header.
+    InvalidEndpoint(String),
+    /// An endpoint name isn't a valid structured field key.
+    InvalidEndpointName(String),
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::EmptyGroup => f.write_str("empty group name"),
+            HeaderError::InvalidFraction(fraction) => {
+                write!(f, "invalid sampling fraction: {}", fraction)
+            }
+            HeaderError::NoEndpoints => f.write_str("no endpoints"),
+            HeaderError::InvalidEndpoint(url) => write!(f, "invalid endpoint: {:?}", url),
+            HeaderError::InvalidEndpointName(name) => {
+                write!(f, "invalid endpoint name: {:?}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// NelHeaderBuilder builds the value of a NEL response header.
+pub struct NelHeaderBuilder {
+    header: NelHeader,
+}
+
+impl NelHeaderBuilder {
+    /// Starts a policy that sends reports to `report_to` for the next `max_age` seconds.
+    pub fn new<T: ToString>(report_to: T, max_age: u64) -> Self {
+        NelHeaderBuilder {
+            header: NelHeader {
+                report_to: report_to.to_string(),
+                max_age,
+                include_subdomains: false,
+                success_fraction: 0.0,
+                failure_fraction: default_failure_fraction(),
+                request_headers: Vec::new(),
+                response_headers: Vec::new(),
+            },
+        }
+    }
+
+    pub fn include_subdomains(mut self, include_subdomains: bool) -> Self {
+        self.header.include_subdomains = include_subdomains;
+        self
+    }
+    pub fn success_fraction(mut self, fraction: f32) -> Self {
+        self.header.success_fraction = fraction;
+        self
+    }
+    pub fn failure_fraction(mut self, fraction: f32) -> Self {
+        self.header.failure_fraction = fraction;
+        self
+    }
+    /// Asks for the value of a request header to be included in reports.
+    pub fn request_header<T: ToString>(mut self, name: T) -> Self {
+        self.header.request_headers.push(name.to_string());
+        self
+    }
+    /// Asks for the value of a response header to be included in reports.
+    pub fn response_header<T: ToString>(mut self, name: T) -> Self {
+        self.header.response_headers.push(name.to_string());
+        self
+    }
+
+    /// Validates the policy with the same rules as nel_header, and returns the header value.
+    pub fn build(self) -> Result<String, HeaderError> {
+        self.header.validate()?;
+        Ok(serde_json::to_string(&self.header).unwrap())
+    }
+}
+
+/// ReportToHeaderBuilder builds the value of a Report-To response header.
+pub struct ReportToHeaderBuilder {
+    header: ReportToHeader,
+}
+
+impl ReportToHeaderBuilder {
+    /// Starts a group named `group` that's valid for the next `max_age` seconds.
+    pub fn new<T: ToString>(group: T, max_age: u64) -> Self {
+        ReportToHeaderBuilder {
+            header: ReportToHeader {
+                group: group.to_string(),
+                max_age,
+                endpoints: Vec::new(),
+            },
+        }
+    }
+
+    pub fn endpoint<T: ToString>(mut self, url: T) -> Self {
+        self.header.endpoints.push(ReportEndpoint {
+            url: url.to_string(),
+        });
+        self
+    }
+
+    /// Validates the group with the same rules as report_to_header, and returns the header value.
+    pub fn build(self) -> Result<String, HeaderError> {
+        self.header.validate()?;
+        Ok(serde_json::to_string(&self.header).unwrap())
+    }
+}
+
+/// ReportingEndpointsBuilder builds the value of a Reporting-Endpoints response header.
+#[derive(Default)]
+pub struct ReportingEndpointsBuilder {
+    endpoints: Vec<(String, String)>,
+}
+
+impl ReportingEndpointsBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds an endpoint named `name`, which policies can refer to as their group.
+    pub fn endpoint<N: ToString, U: ToString>(mut self, name: N, url: U) -> Self {
+        self.endpoints.push((name.to_string(), url.to_string()));
+        self
+    }
+
+    /// Validates the endpoints and returns the header value, a structured field dictionary.
+    pub fn build(self) -> Result<String, HeaderError> {
+        if self.endpoints.is_empty() {
+            return Err(HeaderError::NoEndpoints);
+        }
+
+        let mut members = Vec::with_capacity(self.endpoints.len());
+        for (name, url) in self.endpoints {
+            if !is_sf_key(&name) {
+                return Err(HeaderError::InvalidEndpointName(name));
+            }
+            if url.is_empty() || !url.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+                return Err(HeaderError::InvalidEndpoint(url));
+            }
+            let url = url.replace('\\', "\\\\").replace('"', "\\\"");
+            members.push(format!("{}=\"{}\"", name, url));
+        }
+        Ok(members.join(", "))
+    }
+}
+
+/// Parses the value of a Reporting-Endpoints header, a structured field dictionary, into pairs
+/// of endpoint name and URL. Members whose value isn't a string are ignored, as are parameters,
+/// and a later member replaces an earlier one of the same name.
+pub(crate) fn parse_reporting_endpoints(hdr: &str) -> Result<Vec<(String, String)>, HeaderError> {
+    let invalid = |rest: &str| HeaderError::InvalidEndpoint(rest.to_string());
+
+    let mut endpoints: Vec<(String, String)> = Vec::new();
+    let mut rest = hdr.trim_matches(' ');
+    while !rest.is_empty() {
+        let (name, after) =
+            parse_sf_key(rest).ok_or_else(|| HeaderError::InvalidEndpointName(rest.to_string()))?;
+        // A member without a value is the boolean true.
+        let (url, after) = match after.strip_prefix('=') {
+            Some(value) => parse_sf_item(value).ok_or_else(|| invalid(value))?,
+            None => (None, skip_sf_params(after).ok_or_else(|| invalid(after))?),
+        };
+
+        endpoints.retain(|(existing, _)| existing != name);
+        if let Some(url) = url {
+            if url.is_empty() {
+                return Err(HeaderError::InvalidEndpoint(url));
+            }
+            endpoints.push((name.to_string(), url));
+        }
+
+        // Members are separated by a comma, which can't be trailing.
+        rest = after.trim_start_matches(is_ows);
+        if !rest.is_empty() {
+            rest = rest
+                .strip_prefix(',')
+                .ok_or_else(|| invalid(rest))?
+                .trim_start_matches(is_ows);
+            if rest.is_empty() {
+                return Err(invalid(after));
+            }
+        }
+    }
+
+    if endpoints.is_empty() {
+        return Err(HeaderError::NoEndpoints);
+    }
+    Ok(endpoints)
+}
+
+fn is_ows(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Parses a structured field item or inner list, with its parameters, at the start of `input`.
+/// Returns the item's value if it's a string, along with the rest of the input.
+fn parse_sf_item(input: &str) -> Option<(Option<String>, &str)> {
+    let (value, rest) = match input.strip_prefix('(') {
+        Some(mut rest) => {
+            // An inner list is items separated by spaces, in parentheses.
+            loop {
+                rest = rest.trim_start_matches(' ');
+                if let Some(after) = rest.strip_prefix(')') {
+                    break (None, after);
+                }
+                let (_, after) = parse_sf_bare_item(rest)?;
+                rest = skip_sf_params(after)?;
+                if !rest.starts_with([' ', ')']) {
+                    return None;
+                }
+            }
+        }
+        None => parse_sf_bare_item(input)?,
+    };
+    Some((value, skip_sf_params(rest)?))
+}
+
+/// Skips the parameters at the start of `input`, returning the rest.
+fn skip_sf_params(mut input: &str) -> Option<&str> {
+    while let Some(param) = input.strip_prefix(';') {
+        let (_, rest) = parse_sf_key(param.trim_start_matches(' '))?;
+        input = match rest.strip_prefix('=') {
+            Some(value) => parse_sf_bare_item(value)?.1,
+            None => rest,
+        };
+    }
+    Some(input)
+}
+
+/// Parses a structured field bare item at the start of `input`. Returns the item's value if it's
+/// a string, along with the rest of the input.
+fn parse_sf_bare_item(input: &str) -> Option<(Option<String>, &str)> {
+    let first = input.chars().next()?;
+    let rest = match first {
+        '"' => {
+            let (value, rest) = parse_sf_string(input)?;
+            return Some((Some(value), rest));
+        }
+        // Integer or decimal.
+        '-' | '0'..='9' => {
+            let number = input.strip_prefix('-').unwrap_or(input);
+            let rest = skip_digits(number)?;
+            match rest.strip_prefix('.') {
+                Some(fraction) => skip_digits(fraction)?,
+                None => rest,
+            }
+        }
+        // Byte sequence.
+        ':' => {
+            let bytes = &input[1..];
+            let end = bytes.find(':')?;
+            if !bytes[..end]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '='))
+            {
+                return None;
+            }
+            &bytes[end + 1..]
+        }
+        // Boolean.
+        '?' => input
+            .strip_prefix("?0")
+            .or_else(|| input.strip_prefix("?1"))?,
+        // Token.
+        c if c.is_ascii_alphabetic() || c == '*' => {
+            let end = input
+                .find(|c: char| !(is_tchar(c) || c == ':' || c == '/'))
+                .unwrap_or(input.len());
+            &input[end..]
+        }
+        _ => return None,
+    };
+    Some((None, rest))
+}
+
+/// Skips one or more digits at the start of `input`, returning the rest.
+fn skip_digits(input: &str) -> Option<&str> {
+    let end = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    if end == 0 {
+        None
+    } else {
+        Some(&input[end..])
+    }
+}
+
+/// Returns true if `c` is allowed in an HTTP token.
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Parses a structured field string at the start of `input`, returning it unescaped along with
+/// the rest of the input.
+fn parse_sf_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                (_, escaped @ ('"' | '\\')) => value.push(escaped),
+                _ => return None,
+            },
+            '"' => return Some((value, chars.as_str())),
+            c if (' '..='~').contains(&c) => value.push(c),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Parses a structured field key at the start of `input`, returning it along with the rest of
+/// the input.
+fn parse_sf_key(input: &str) -> Option<(&str, &str)> {
+    let end = input
+        .find(|c: char| {
+            !(c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.' | '*'))
+        })
+        .unwrap_or(input.len());
+    let (key, rest) = input.split_at(end);
+    if is_sf_key(key) {
+        Some((key, rest))
+    } else {
+        None
+    }
+}
+
+/// Returns true if `key` is a valid structured field key, per RFC 8941.
+fn is_sf_key(key: &str) -> bool {
+    let mut bytes = key.bytes();
+    match bytes.next() {
+        Some(b) if b.is_ascii_lowercase() || b == b'*' => {}
+        _ => return false,
+    }
+    bytes.all(|b| {
+        b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'_' | b'-' | b'.' | b'*')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nel_header() {
+        let hdr = NelHeaderBuilder::new("default", 86400)
+            .success_fraction(0.5)
+            .build()
+            .unwrap();
+        let parsed: NelHeader = serde_json::from_str(&hdr).unwrap();
+        assert_eq!(parsed.report_to, "default");
+        assert_eq!(parsed.success_fraction, 0.5);
+        assert_eq!(parsed.failure_fraction, 1.0);
+        assert!(parsed.request_headers.is_empty());
+        assert!(!hdr.contains("headers"));
+
+        let hdr = NelHeaderBuilder::new("default", 86400)
+            .request_header("If-None-Match")
+            .response_header("ETag")
+            .build()
+            .unwrap();
+        let parsed: NelHeader = serde_json::from_str(&hdr).unwrap();
+        assert_eq!(parsed.request_headers, ["If-None-Match"]);
+        assert_eq!(parsed.response_headers, ["ETag"]);
+
+        let err = NelHeaderBuilder::new("default", 86400)
+            .failure_fraction(1.5)
+            .build();
+        assert_eq!(err, Err(HeaderError::InvalidFraction(1.5)));
+        let err = NelHeaderBuilder::new("", 86400).build();
+        assert_eq!(err, Err(HeaderError::EmptyGroup));
+    }
+
+    #[test]
+    fn report_to_header() {
+        let hdr = ReportToHeaderBuilder::new("default", 86400)
+            .endpoint("https://example.com/reports")
+            .build()
+            .unwrap();
+        assert_eq!(
+            hdr,
+            r#"{"group":"default","max_age":86400,"endpoints":[{"url":"https://example.com/reports"}]}"#
+        );
+
+        let err = ReportToHeaderBuilder::new("default", 86400).build();
+        assert_eq!(err, Err(HeaderError::NoEndpoints));
+    }
+
+    #[test]
+    fn reporting_endpoints_header() {
+        let hdr = ReportingEndpointsBuilder::new()
+            .endpoint("default", "https://example.com/reports")
+            .endpoint("ops", "https://example.com/ops")
+            .build()
+            .unwrap();
+        assert_eq!(
+            hdr,
+            r#"default="https://example.com/reports", ops="https://example.com/ops""#
+        );
+
+        let parsed = parse_reporting_endpoints(&hdr).unwrap();
+        assert_eq!(
+            parsed,
+            [
+                (
+                    "default".to_string(),
+                    "https://example.com/reports".to_string()
+                ),
+                ("ops".to_string(), "https://example.com/ops".to_string()),
+            ]
+        );
+        let parsed = parse_reporting_endpoints(
+            r#"a="https://example.com/\"q\"";x=1,b="https://b.example/""#,
+        )
+        .unwrap();
+        assert_eq!(parsed[0].1, r#"https://example.com/"q""#);
+        assert_eq!(parsed[1].0, "b");
+        assert_eq!(
+            parse_reporting_endpoints("default"),
+            Err(HeaderError::NoEndpoints)
+        );
+        assert_eq!(
+            parse_reporting_endpoints(r#"default="https://example.com/"#),
+            Err(HeaderError::InvalidEndpoint(
+                r#""https://example.com/"#.to_string()
+            ))
+        );
+
+        // Parameters and members that aren't strings are skipped, even if they contain commas.
+        let parsed = parse_reporting_endpoints(
+            r#"a="https://a.example/";p="x,y";q=?1, n=1.5, l=(tok "s");r, a="https://c.example/""#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            [("a".to_string(), "https://c.example/".to_string())]
+        );
+        for hdr in [
+            r#"a="https://a.example/" junk"#,
+            r#"a="https://a.example/","#,
+            r#"a="https://a.example/";p="x, b="https://b.example/""#,
+            r#"a="https://a.example/";P=1"#,
+        ] {
+            assert!(parse_reporting_endpoints(hdr).is_err(), "{}", hdr);
+        }
+
+        let err = ReportingEndpointsBuilder::new()
+            .endpoint("Default", "https://example.com/reports")
+            .build();
+        assert_eq!(
+            err,
+            Err(HeaderError::InvalidEndpointName("Default".to_string()))
+        );
+    }
+}
@@ -1,69 +1,110 @@
 #![recursion_limit = "512"]
 
+mod aggregate;
+mod classify;
+#[cfg(feature = "collector")]
+mod collector;
+mod compression;
+#[cfg(feature = "connector")]
+mod connector;
 mod error;
+mod generic;
+mod header;
+#[cfg(feature = "tower")]
+mod layer;
+#[cfg(feature = "middleware")]
+mod middleware;
+mod parse;
+mod ratelimit;
 mod report;
 
 #[macro_use]
 extern crate lazy_static;
 
+use aggregate::Aggregator;
 use deadqueue::limited::Queue;
 use futures_util::{future::Fuse, pin_mut, select, Future, FutureExt};
+use header::{parse_reporting_endpoints, NelHeader, ReportToHeader};
 use rand::{random, seq::SliceRandom, thread_rng};
-use report::FailedReport;
-use serde::{Deserialize, Serialize};
+use ratelimit::RateLimiter;
+use report::{FailedReport, QueuedReport};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 use ttl_cache::TtlCache;
 use url::Url;
 
-pub use error::Error;
-pub use report::NELReport;
+pub use classify::ResponseClassifier;
+#[cfg(feature = "collector")]
+pub use collector::{Collector, ReportSink};
+pub use compression::{Compression, ContentEncoding, Payload};
+#[cfg(feature = "connector")]
+pub use connector::{
+    ConnectInfo, ConnectTracker, NelConnection, NelConnector, NelResolver, Tracked,
+};
+pub use error::{DnsError, Error, HttpError, Phase, Stage, TlsError, TransportError};
+pub use generic::{
+    Body, CrashBody, CspViolationBody, CustomBody, DeprecationBody, InterventionBody, Report,
+};
+pub use header::{HeaderError, NelHeaderBuilder, ReportToHeaderBuilder, ReportingEndpointsBuilder};
+#[cfg(feature = "tower")]
+pub use layer::{NelLayer, NelService};
+#[cfg(feature = "middleware")]
+pub use middleware::NelMiddleware;
+pub use parse::{parse_reports, ParseError, ReceivedReport};
+pub use ratelimit::{rate_limited_reports, RateLimit};
+pub use report::{NELReport, ReportFormat};
 
 const RETRY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Reporting-Endpoints has no max_age, because its endpoints last as long as the document that
+/// set them. Without a document, they're kept for a day unless replaced.
+const REPORTING_ENDPOINTS_MAX_AGE: Duration = Duration::from_secs(86400);
 
 #[derive(Clone)]
 struct NELPolicy {
     report_to: String,
     success_fraction: f32,
     failure_fraction: f32,
+    /// The IP address of the server the policy was received from, if known.
+    received_ip: Option<IpAddr>,
+    /// Lowercase names of the request and response headers to include in reports.
+    request_headers: Vec<String>,
+    response_headers: Vec<String>,
+}
+
+/// EndpointGroup is a cached group of reporting endpoints.
+#[derive(Clone)]
+struct EndpointGroup {
+    endpoints: Vec<String>,
+    /// The format the group's endpoints expect, which depends on the header that set it.
+    format: ReportFormat,
 }
 
 lazy_static! {
     static ref NEL_POLICY_CACHE: Mutex<TtlCache<String, NELPolicy>> = Mutex::new(TtlCache::new(50));
-    static ref GROUP_POLICY_CACHE: Mutex<TtlCache<String, Vec<String>>> =
+    static ref GROUP_POLICY_CACHE: Mutex<TtlCache<String, EndpointGroup>> =
         Mutex::new(TtlCache::new(50));
-    static ref REPORT_QUEUE: Queue<NELReport> = Queue::new(256);
-}
-
-#[derive(Serialize, Deserialize)]
-struct NelHeader {
-    /// Name of group to send reports to.
-    report_to: String,
-    /// Lifetime of policy in seconds.
-    max_age: u64,
-    #[serde(default)]
-    include_subdomains: bool,
-    #[serde(default)]
-    success_fraction: f32,
-    #[serde(default = "default_failure_fraction")]
-    failure_fraction: f32,
-}
-
-const fn default_failure_fraction() -> f32 {
-    1.0
+    static ref REPORT_QUEUE: Queue<QueuedReport> = Queue::new(256);
+    static ref RATE_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new(None, None, None));
 }
 
 /// nel_header takes the value of a NEL header and caches the specified policy.
 pub fn nel_header(host: &str, hdr: &str) {
+    nel_header_from(host, hdr, None)
+}
+
+/// nel_header_from is the same as nel_header, but also records the IP address of the server the
+/// header was received from. Reports about requests later served by a different IP address are
+/// downgraded to dns.address_changed, as the spec requires.
+pub fn nel_header_from(host: &str, hdr: &str, received_ip: Option<IpAddr>) {
     let parsed = match serde_json::from_str::<NelHeader>(hdr) {
         Ok(parsed) => parsed,
         Err(_) => return,
     };
 
-    let valid = !parsed.report_to.is_empty()
-        && (0.0..=1.0).contains(&parsed.success_fraction)
-        && (0.0..=1.0).contains(&parsed.failure_fraction);
-    if !valid {
+    if parsed.validate().is_err() {
         return;
     }
 
@@ -75,6 +116,9 @@ pub fn nel_header(host: &str, hdr: &str) {
                 report_to: parsed.report_to,
                 success_fraction: parsed.success_fraction,
                 failure_fraction: parsed.failure_fraction,
+                received_ip,
+                request_headers: lowercase(parsed.request_headers),
+                response_headers: lowercase(parsed.response_headers),
             };
             guard.insert(
                 host.to_string(),
@@ -85,18 +129,8 @@ pub fn nel_header(host: &str, hdr: &str) {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct ReportToHeader {
-    /// Name of this group of endpoints.
-    group: String,
-    /// Lifetime of policy in seconds.
-    max_age: u64,
-    endpoints: Vec<ReportEndpoint>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct ReportEndpoint {
-    url: String,
+fn lowercase(names: Vec<String>) -> Vec<String> {
+    names.iter().map(|name| name.to_ascii_lowercase()).collect()
 }
 
 /// report_to_header takes the value of the Report-To header and saves any group endpoint URLs.
@@ -106,10 +140,7 @@ pub fn report_to_header(host: &str, hdr: &str) {
         Err(_) => return,
     };
 
-    let valid = !parsed.group.is_empty()
-        && !parsed.endpoints.is_empty()
-        && parsed.endpoints.iter().all(|ep| !ep.url.is_empty());
-    if !valid {
+    if parsed.validate().is_err() {
         return;
     }
 
@@ -119,17 +150,188 @@ pub fn report_to_header(host: &str, hdr: &str) {
         if parsed.max_age == 0 {
             guard.remove(&key);
         } else {
-            let endpoints = parsed.endpoints.iter().map(|ep| ep.url.clone()).collect();
-            guard.insert(key, endpoints, Duration::from_secs(parsed.max_age));
+            let group = EndpointGroup {
+                endpoints: parsed.endpoints.iter().map(|ep| ep.url.clone()).collect(),
+                format: ReportFormat::Legacy,
+            };
+            guard.insert(key, group, Duration::from_secs(parsed.max_age));
         }
     }
 }
 
+/// reporting_endpoints_header takes the value of the Reporting-Endpoints header and saves each
+/// endpoint as a group of its own, replacing the endpoints of any earlier Reporting-Endpoints
+/// header from `host`. Reports to these groups use the Reporting API v1 format.
+pub fn reporting_endpoints_header(host: &str, hdr: &str) {
+    let endpoints = match parse_reporting_endpoints(hdr) {
+        Ok(endpoints) => endpoints,
+        Err(_) => return,
+    };
+
+    if let Ok(mut guard) = GROUP_POLICY_CACHE.lock() {
+        let prefix = format!("{}:", host);
+        let dropped: Vec<String> = guard
+            .iter()
+            .filter(|(key, group)| {
+                group.format == ReportFormat::V1
+                    && matches!(key.strip_prefix(&prefix), Some(name)
+                        if !endpoints.iter().any(|(endpoint, _)| endpoint == name))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in dropped {
+            guard.remove(&key);
+        }
+
+        for (name, url) in endpoints {
+            let group = EndpointGroup {
+                endpoints: vec![url],
+                format: ReportFormat::V1,
+            };
+            guard.insert(
+                format!("{}:{}", host, name),
+                group,
+                REPORTING_ENDPOINTS_MAX_AGE,
+            );
+        }
+    }
+}
+
+/// Feeds the NEL and Report-To headers of a response from `host`, served by `server_ip`, into the
+/// policy caches.
+#[cfg(any(feature = "middleware", feature = "tower"))]
+pub(crate) fn policy_headers<I, K, V>(host: &str, server_ip: Option<IpAddr>, headers: I)
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<[u8]>,
+{
+    for (name, value) in headers {
+        let name = name.as_ref();
+        let value = match std::str::from_utf8(value.as_ref()) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if name.eq_ignore_ascii_case("nel") {
+            nel_header_from(host, value, server_ip);
+        } else if name.eq_ignore_ascii_case("report-to") {
+            report_to_header(host, value);
+        } else if name.eq_ignore_ascii_case("reporting-endpoints") {
+            reporting_endpoints_header(host, value);
+        }
+    }
+}
+
+/// Returns the URL to put in a report, without the credentials or fragment that the spec
+/// requires reports to leave out.
+#[cfg(any(feature = "middleware", feature = "tower"))]
+pub(crate) fn sanitize_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.set_fragment(None);
+            url.into()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Returns the ALPN protocol ID for an HTTP version, which is what NEL reports use. It's a macro
+/// because reqwest and the tower layer use different versions of the http crate, so `$version`
+/// names the Version type to match against.
+#[cfg(any(feature = "middleware", feature = "tower"))]
+macro_rules! alpn_protocol {
+    ($version:ident, $value:expr) => {
+        match $value {
+            $version::HTTP_09 => Some("http/0.9"),
+            $version::HTTP_10 => Some("http/1.0"),
+            $version::HTTP_11 => Some("http/1.1"),
+            $version::HTTP_2 => Some("h2"),
+            $version::HTTP_3 => Some("h3"),
+            _ => None,
+        }
+    };
+}
+#[cfg(any(feature = "middleware", feature = "tower"))]
+pub(crate) use alpn_protocol;
+
 /// submit_report adds a report to the queue to be sent to the server.
 pub fn submit_report(report: NELReport) {
+    enqueue(QueuedReport::Network(report));
+}
+
+/// submit_to_group adds a report to the queue, to be sent to one of the endpoints in `origin`'s
+/// `group`. The group can come from either a Report-To or a Reporting-Endpoints header, and no NEL
+/// policy is needed. `origin` is a URL such as `https://example.com`, which the report is about.
+pub fn submit_to_group<B: Body>(origin: &str, group: &str, body: &B) {
+    let mut report = Report::new(origin.to_string(), body);
+    report.group = group.to_string();
+    submit_generic_report(report);
+}
+
+/// submit_generic_report adds a report of another type to the queue, to be sent to its endpoint
+/// group. Unlike NEL reports, it doesn't need a NEL policy, only the group.
+pub fn submit_generic_report(report: Report) {
+    enqueue(QueuedReport::Other(report));
+}
+
+/// enqueue adds a report to the queue, unless its origin is over the per-origin rate limit.
+fn enqueue(mut report: QueuedReport) {
+    if let Some(host) = report_host(&report) {
+        let admitted = match RATE_LIMITER.lock() {
+            Ok(mut limiter) => limiter.admit_origin(&host, Instant::now()),
+            Err(_) => true,
+        };
+        if !admitted {
+            return;
+        }
+        if let QueuedReport::Network(report) = &mut report {
+            retain_policy_headers(&host, report);
+        }
+    }
     let _ = REPORT_QUEUE.try_push(report);
 }
 
+/// retain_policy_headers drops the captured headers that the origin's NEL policy doesn't ask
+/// for, so that they aren't held in the queue and don't keep otherwise identical reports from
+/// being aggregated. They're filtered again when the report is sent, in case the policy changed.
+fn retain_policy_headers(host: &str, report: &mut NELReport) {
+    if let Ok(guard) = NEL_POLICY_CACHE.lock() {
+        match guard.get(host) {
+            Some(policy) => {
+                report.retain_headers(&policy.request_headers, &policy.response_headers)
+            }
+            None => report.retain_headers(&[], &[]),
+        }
+    }
+}
+
+/// ReportingConfig tunes how handle_reports_with_config processes queued reports.
+#[derive(Debug, Clone, Default)]
+pub struct ReportingConfig {
+    /// If set, identical reports received within this window are collapsed into a single
+    /// report carrying a count, instead of being submitted one by one.
+    pub aggregation_window: Option<Duration>,
+
+    /// Limits the rate of reports submitted across all origins and endpoints.
+    pub global_rate_limit: Option<RateLimit>,
+    /// Limits the rate of reports submitted about each origin. This limit is applied as reports
+    /// are queued, once handle_reports_with_config has started.
+    pub origin_rate_limit: Option<RateLimit>,
+    /// Limits the rate of reports submitted to each reporting endpoint.
+    pub endpoint_rate_limit: Option<RateLimit>,
+
+    /// If set, report payloads are compressed before being handed to the transport.
+    pub compression: Option<Compression>,
+
+    /// Overrides the format of reports sent to endpoint groups, by group name. Otherwise groups
+    /// from Report-To get the legacy format, and those from Reporting-Endpoints get v1.
+    pub group_formats: HashMap<String, ReportFormat>,
+    /// The user agent included in Reporting API v1 reports.
+    pub user_agent: Option<String>,
+}
+
 /// handle_reports receives NEL reports and submits them to the reporting endpoint.
 ///
 /// As input, it takes:
@@ -142,6 +344,23 @@ where
     G: Fn(String, String) -> GFut,
     FFut: Future<Output = ()>,
     GFut: Future<Output = bool>,
+{
+    // Without compression configured, payloads are always plain JSON.
+    let post = |uri, payload: Payload| post(uri, String::from_utf8_lossy(&payload.body).into());
+    handle_reports_with_config(ReportingConfig::default(), sleep, post).await
+}
+
+/// handle_reports_with_config is the same as handle_reports, but takes a ReportingConfig. The
+/// POST method is given a Payload, whose content encoding must be sent along with the body.
+pub async fn handle_reports_with_config<F, G, FFut, GFut>(
+    config: ReportingConfig,
+    sleep: F,
+    post: G,
+) where
+    F: Fn(Duration) -> FFut,
+    G: Fn(String, Payload) -> GFut,
+    FFut: Future<Output = ()>,
+    GFut: Future<Output = bool>,
 {
     let pop = REPORT_QUEUE.pop().fuse();
 
@@ -149,51 +368,68 @@ where
     let fail_timeout = Fuse::terminated();
     let mut next_failed: Option<FailedReport> = None;
 
-    pin_mut!(pop, fail_timeout);
+    let mut aggregator = Aggregator::new(256);
+    if let Ok(mut limiter) = RATE_LIMITER.lock() {
+        *limiter = RateLimiter::new(
+            config.global_rate_limit,
+            config.origin_rate_limit,
+            config.endpoint_rate_limit,
+        );
+    }
+    let flush_timeout = Fuse::terminated();
+
+    pin_mut!(pop, fail_timeout, flush_timeout);
 
     // TODO: Submit many reports to the same group at once.
     loop {
         select! {
             report = pop => {
-                // Submit report.
-                let payload = report.serialize();
-                let success = match choose_endpoint(&report, true) {
-                    Some(endpoint) => post(endpoint, payload).await,
-                    None => true, // No cached endpoint to submit report to.
+                let report = match (report, config.aggregation_window) {
+                    (QueuedReport::Network(report), Some(window)) => {
+                        // Hold on to the report until the window ends, unless the aggregator is full.
+                        if aggregator.is_empty() {
+                            flush_timeout.set(sleep(window).fuse());
+                        }
+                        aggregator.add(report).map(QueuedReport::Network)
+                    }
+                    (report, _) => Some(report),
                 };
 
-                // If submitting the report failed, save it and try again later.
-                if !success {
-                    let failed = FailedReport{
-                        last_try: Instant::now(),
-                        original: report,
-                    };
-                    if next_failed.is_none() {
-                        fail_timeout.set(sleep(RETRY_TIMEOUT).fuse());
-                        next_failed = Some(failed);
-                    } else {
-                        let _ = failed_queue.try_push(failed);
+                if let Some(report) = report {
+                    // Submit report. If submitting the report failed, save it and try again later.
+                    if let Some(failed) = deliver(&post, report, &config).await {
+                        schedule_retry(failed, &sleep, &mut next_failed, &failed_queue, fail_timeout.as_mut());
                     }
                 }
 
                 // Start waiting for the next report.
                 pop.set(REPORT_QUEUE.pop().fuse());
             },
+            _ = flush_timeout => {
+                // Submit every report aggregated during the window that just ended.
+                for report in aggregator.drain().into_iter().map(QueuedReport::Network) {
+                    if let Some(failed) = deliver(&post, report, &config).await {
+                        schedule_retry(failed, &sleep, &mut next_failed, &failed_queue, fail_timeout.as_mut());
+                    }
+                }
+                flush_timeout.set(Fuse::terminated());
+            },
             _ = fail_timeout => {
                 // Submit next_failed report.
-                let report = &next_failed.as_ref().unwrap().original;
-                let payload = report.serialize();
-                let success = match choose_endpoint(report, false) {
-                    Some(endpoint) => post(endpoint, payload).await,
+                let failed = next_failed.as_mut().unwrap();
+                let success = match choose_endpoint(&mut failed.original, false) {
+                    Some(target) => {
+                        let payload = encode(&failed.original, failed.sampling_fraction, &target, &config);
+                        post(target.endpoint, payload).await
+                    }
                     None => true, // No cached endpoint to submit report to.
                 };
 
                 // If submitting the report failed, save it and try again later.
                 if !success {
-                    let _ = failed_queue.try_push(FailedReport{
-                        last_try: Instant::now(),
-                        original: next_failed.unwrap().original,
-                    });
+                    let mut failed = next_failed.unwrap();
+                    failed.last_try = Instant::now();
+                    let _ = failed_queue.try_push(failed);
                 }
 
                 // Pop the next failed report and prepare a timer.
@@ -212,38 +448,420 @@ where
     }
 }
 
-fn choose_endpoint(report: &NELReport, evaluate_drop: bool) -> Option<String> {
-    // Pull up the policies that correspond to this report.
-    let host = match &report.host_override {
-        Some(host) => host.clone(),
+/// deliver samples and rate limits a new report, then posts it to one of its endpoints. If
+/// submitting the report failed, it is returned so that it can be retried.
+async fn deliver<G, GFut>(
+    post: &G,
+    mut report: QueuedReport,
+    config: &ReportingConfig,
+) -> Option<FailedReport>
+where
+    G: Fn(String, Payload) -> GFut,
+    GFut: Future<Output = bool>,
+{
+    // No cached endpoint to submit report to, or the report was sampled out.
+    let target = choose_endpoint(&mut report, true)?;
+    let host = report_host(&report)?;
+    let limiter_fraction =
+        RATE_LIMITER
+            .lock()
+            .ok()?
+            .admit(&host, &target.endpoint, Instant::now())?;
+
+    let sampling_fraction = target.sampling_fraction * limiter_fraction;
+    let payload = encode(&report, sampling_fraction, &target, config);
+    if post(target.endpoint, payload).await {
+        None
+    } else {
+        Some(FailedReport {
+            last_try: Instant::now(),
+            original: report,
+            sampling_fraction,
+        })
+    }
+}
+
+/// encode serializes a report in the format its endpoint group expects, and compresses it.
+fn encode(
+    report: &QueuedReport,
+    sampling_fraction: f32,
+    target: &Target,
+    config: &ReportingConfig,
+) -> Payload {
+    let format = match config.group_formats.get(&target.group) {
+        Some(format) => *format,
+        None => target.format,
+    };
+    let json = report.serialize_as(sampling_fraction, format, config.user_agent.as_deref());
+    Payload::encode(json, config.compression.as_ref())
+}
+
+/// schedule_retry saves a report that failed to submit so that it's tried again later.
+fn schedule_retry<F, FFut>(
+    failed: FailedReport,
+    sleep: &F,
+    next_failed: &mut Option<FailedReport>,
+    failed_queue: &Queue<FailedReport>,
+    mut fail_timeout: Pin<&mut Fuse<FFut>>,
+) where
+    F: Fn(Duration) -> FFut,
+    FFut: Future<Output = ()>,
+{
+    if next_failed.is_none() {
+        fail_timeout.set(sleep(RETRY_TIMEOUT).fuse());
+        *next_failed = Some(failed);
+    } else {
+        let _ = failed_queue.try_push(failed);
+    }
+}
+
+/// report_host returns the host whose policies apply to a report.
+fn report_host(report: &QueuedReport) -> Option<String> {
+    match report.host_override() {
+        Some(host) => Some(host.to_owned()),
         None => {
-            let report_url = Url::parse(&report.url).ok()?;
-            report_url.host_str()?.to_owned()
+            let report_url = Url::parse(report.url()).ok()?;
+            Some(report_url.host_str()?.to_owned())
         }
-    };
+    }
+}
+
+/// Target is the endpoint chosen for a report.
+struct Target {
+    endpoint: String,
+    group: String,
+    format: ReportFormat,
+    /// The fraction of reports like this one that the origin's policy asks to be sampled.
+    sampling_fraction: f32,
+}
+
+/// choose_endpoint returns a random endpoint to submit the report to. NEL reports go to the group
+/// named by the origin's NEL policy, and may be sampled out or downgraded by it. Other reports go
+/// to the group they name.
+fn choose_endpoint(report: &mut QueuedReport, evaluate_drop: bool) -> Option<Target> {
+    let host = report_host(report)?;
+    match report {
+        QueuedReport::Network(report) => choose_nel_endpoint(&host, report, evaluate_drop),
+        QueuedReport::Other(report) => group_target(&host, &report.group, 1.0),
+    }
+}
+
+/// choose_nel_endpoint applies the origin's NEL policy to a report, and returns a random endpoint
+/// of the policy's group if the report isn't sampled out.
+fn choose_nel_endpoint(host: &str, report: &mut NELReport, evaluate_drop: bool) -> Option<Target> {
+    // Pull up the policy that corresponds to this report.
     let nel_policy = {
         let guard = NEL_POLICY_CACHE.lock().ok()?;
-        let policy = guard.get(&host)?;
+        let policy = guard.get(host)?;
         policy.clone()
     };
+
+    // Don't reveal anything about a request to a server other than the one that set the policy.
+    if moved_server(report, &nel_policy) {
+        report.downgrade();
+    }
+    report.retain_headers(&nel_policy.request_headers, &nel_policy.response_headers);
+
+    // Decide if report should be dropped.
+    let sampling_fraction = if report.is_success() {
+        nel_policy.success_fraction
+    } else {
+        nel_policy.failure_fraction
+    };
+    if evaluate_drop && random::<f32>() >= sampling_fraction {
+        return None;
+    }
+
+    // Return random endpoint if not dropped.
+    group_target(host, &nel_policy.report_to, sampling_fraction)
+}
+
+/// group_target returns a random endpoint of one of the origin's endpoint groups.
+fn group_target(host: &str, group: &str, sampling_fraction: f32) -> Option<Target> {
     let group_policy = {
-        let group_policy_key = format!("{}:{}", host, &nel_policy.report_to);
+        let group_policy_key = format!("{}:{}", host, group);
         let guard = GROUP_POLICY_CACHE.lock().ok()?;
         let policy = guard.get(&group_policy_key)?;
         policy.clone()
     };
 
-    // Decide if report should be dropped.
-    if evaluate_drop {
-        if report.is_success() {
-            if random::<f32>() >= nel_policy.success_fraction {
-                return None;
-            }
-        } else if random::<f32>() >= nel_policy.failure_fraction {
-            return None;
+    let endpoint = group_policy.endpoints.choose(&mut thread_rng())?.clone();
+    Some(Target {
+        endpoint,
+        group: group.to_string(),
+        format: group_policy.format,
+        sampling_fraction,
+    })
+}
+
+/// moved_server returns true if a report is about a request served by a different IP address
+/// than the one its policy was received from. DNS failures never reached a server, and reports
+/// or policies without an IP address can't be compared.
+fn moved_server(report: &NELReport, policy: &NELPolicy) -> bool {
+    if report.phase() == Phase::Dns {
+        return false;
+    }
+    match (policy.received_ip, report.server_ip.parse::<IpAddr>()) {
+        (Some(received_ip), Ok(server_ip)) => received_ip != server_ip,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that take reports from the queue hold this, so that they don't take each other's.
+    pub(crate) static QUEUE: Mutex<()> = Mutex::new(());
+
+    fn choose(report: &mut NELReport) -> Option<Target> {
+        let host = Url::parse(&report.url)
+            .unwrap()
+            .host_str()
+            .unwrap()
+            .to_string();
+        choose_nel_endpoint(&host, report, true)
+    }
+
+    fn cache_policy(host: &str, received_ip: Option<IpAddr>) {
+        nel_header_from(
+            host,
+            r#"{"report_to":"default","max_age":60,"success_fraction":1.0}"#,
+            received_ip,
+        );
+        report_to_header(
+            host,
+            r#"{"group":"default","max_age":60,"endpoints":[{"url":"https://reports.example/"}]}"#,
+        );
+    }
+
+    #[test]
+    fn downgrades_moved_servers() {
+        let host = "downgrade-test.example";
+        cache_policy(host, Some("192.0.2.1".parse().unwrap()));
+
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_server_ip(Some("192.0.2.1:443"));
+        report.set_status_code(200);
+        assert!(choose(&mut report).is_some());
+        assert!(report.is_success());
+
+        report.set_server_ip(Some("198.51.100.1:443"));
+        report.set_elapsed_time(Duration::from_millis(10));
+        report.set_error(Error::Tcp(TransportError::Reset));
+        let target = choose(&mut report).unwrap();
+        assert_eq!(target.endpoint, "https://reports.example/");
+        assert_eq!(report.error(), Some(&Error::Dns(DnsError::AddressChanged)));
+        assert_eq!(report.phase(), Phase::Dns);
+        assert_eq!(report.status_code, 0);
+        assert_eq!(report.elapsed_time, Duration::ZERO);
+        assert_eq!(report.server_ip, "198.51.100.1");
+
+        // Without an IP address on both sides, there's nothing to compare.
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_error(Error::Tcp(TransportError::Reset));
+        choose(&mut report);
+        assert_eq!(report.error(), Some(&Error::Tcp(TransportError::Reset)));
+
+        let host = "downgrade-test-no-ip.example";
+        cache_policy(host, None);
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_server_ip(Some("198.51.100.1"));
+        choose(&mut report);
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn downgrades_moved_ipv6_servers() {
+        let host = "downgrade-test-v6.example";
+        cache_policy(host, Some("2001:db8::1".parse().unwrap()));
+
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_server_ip(Some("[2001:db8::1]:443"));
+        report.set_error(Error::Tcp(TransportError::Reset));
+        choose(&mut report);
+        assert_eq!(report.error(), Some(&Error::Tcp(TransportError::Reset)));
+
+        report.server_ip = "2001:db8::2".to_string();
+        choose(&mut report);
+        assert_eq!(report.error(), Some(&Error::Dns(DnsError::AddressChanged)));
+        assert_eq!(report.server_ip, "2001:db8::2");
+    }
+
+    #[test]
+    fn keeps_policy_headers() {
+        let host = "headers-test.example";
+        nel_header(
+            host,
+            r#"{"report_to":"default","max_age":60,"request_headers":["If-None-Match"],"response_headers":["ETag"]}"#,
+        );
+        report_to_header(
+            host,
+            r#"{"group":"default","max_age":60,"endpoints":[{"url":"https://reports.example/"}]}"#,
+        );
+
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_error(Error::Http(HttpError::Error));
+        report.set_request_headers([("if-none-match", "\"abc\""), ("cookie", "secret")]);
+        report.set_response_headers([("ETag", "\"def\""), ("ETag", "\"ghi\"")]);
+        choose(&mut report).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&report.serialize()).unwrap();
+        let body = &json[0]["body"];
+        assert_eq!(
+            body["request_headers"],
+            serde_json::json!({"if-none-match": ["\"abc\""]})
+        );
+        assert_eq!(
+            body["response_headers"],
+            serde_json::json!({"etag": ["\"def\"", "\"ghi\""]})
+        );
+    }
+
+    #[test]
+    fn aggregates_despite_other_headers() {
+        let host = "aggregate-headers-test.example";
+        nel_header(
+            host,
+            r#"{"report_to":"default","max_age":60,"request_headers":["If-None-Match"]}"#,
+        );
+
+        let mut aggregator = Aggregator::new(16);
+        for (date, request_id) in [("Mon", "1"), ("Tue", "2")] {
+            let mut report = NELReport::new(format!("https://{}/", host));
+            report.set_error(Error::Http(HttpError::Error));
+            report
+                .set_request_headers([("if-none-match", "\"abc\""), ("x-request-id", request_id)]);
+            report.set_response_headers([("date", date)]);
+            retain_policy_headers(host, &mut report);
+            assert!(aggregator.add(report).is_none());
         }
+
+        let reports = aggregator.drain();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].count(), 2);
+        assert_eq!(reports[0].request_headers().len(), 1);
+        assert!(reports[0].response_headers().is_empty());
     }
 
-    // Return random endpoint if not dropped.
-    Some(group_policy.choose(&mut thread_rng())?.clone())
+    #[test]
+    fn reporting_endpoints_format() {
+        let host = "format-test.example";
+        nel_header(host, r#"{"report_to":"v1","max_age":60}"#);
+        reporting_endpoints_header(host, r#"v1="https://reports.example/v1""#);
+
+        let mut report = NELReport::new(format!("https://{}/", host));
+        report.set_error(Error::Tcp(TransportError::Refused));
+        let target = choose(&mut report).unwrap();
+        assert_eq!(target.endpoint, "https://reports.example/v1");
+        assert_eq!(target.format, ReportFormat::V1);
+
+        let config = ReportingConfig {
+            user_agent: Some("nel-test".to_string()),
+            ..Default::default()
+        };
+        let payload = encode(
+            &QueuedReport::Network(report.clone()),
+            1.0,
+            &target,
+            &config,
+        );
+        let json: serde_json::Value = serde_json::from_slice(&payload.body).unwrap();
+        assert_eq!(json[0]["user_agent"], "nel-test");
+        assert_eq!(json[0]["body"]["samplingFraction"], 1.0);
+        assert_eq!(json[0]["body"]["type"], "tcp.refused");
+
+        let mut config = config;
+        config
+            .group_formats
+            .insert("v1".to_string(), ReportFormat::Legacy);
+        let payload = encode(
+            &QueuedReport::Network(report.clone()),
+            1.0,
+            &target,
+            &config,
+        );
+        let json: serde_json::Value = serde_json::from_slice(&payload.body).unwrap();
+        assert_eq!(json[0].get("user_agent"), None);
+        assert_eq!(json[0]["body"]["sampling_fraction"], 1.0);
+    }
+
+    #[test]
+    fn reporting_endpoints_replace_earlier_ones() {
+        let host = "replace-test.example";
+        report_to_header(
+            host,
+            r#"{"group":"legacy","max_age":60,"endpoints":[{"url":"https://reports.example/"}]}"#,
+        );
+        reporting_endpoints_header(
+            host,
+            r#"a="https://reports.example/a", b="https://reports.example/b""#,
+        );
+        reporting_endpoints_header(host, r#"b="https://reports.example/b2""#);
+
+        assert!(group_target(host, "a", 1.0).is_none());
+        assert_eq!(
+            group_target(host, "b", 1.0).unwrap().endpoint,
+            "https://reports.example/b2"
+        );
+        assert!(group_target(host, "legacy", 1.0).is_some());
+    }
+
+    #[test]
+    fn generic_reports_use_groups() {
+        let host = "generic-test.example";
+        reporting_endpoints_header(host, r#"ops="https://reports.example/ops""#);
+
+        // No NEL policy is needed.
+        let body = CrashBody {
+            reason: Some("oom".to_string()),
+        };
+        let mut report = Report::new(format!("https://{}/", host), &body);
+        report.group = "ops".to_string();
+        let mut report = QueuedReport::Other(report);
+        let target = choose_endpoint(&mut report, true).unwrap();
+        assert_eq!(target.endpoint, "https://reports.example/ops");
+        assert_eq!(target.format, ReportFormat::V1);
+
+        let payload = encode(&report, 1.0, &target, &ReportingConfig::default());
+        let json: serde_json::Value = serde_json::from_slice(&payload.body).unwrap();
+        assert_eq!(json[0]["type"], "crash");
+        assert_eq!(json[0]["body"]["reason"], "oom");
+
+        let mut report = QueuedReport::Other(Report::new(format!("https://{}/", host), &body));
+        assert!(choose_endpoint(&mut report, true).is_none());
+    }
+
+    #[test]
+    fn reports_to_named_groups() {
+        let _guard = QUEUE.lock().unwrap_or_else(|err| err.into_inner());
+        let host = "group-test.example";
+        report_to_header(
+            host,
+            r#"{"group":"ops","max_age":60,"endpoints":[{"url":"https://reports.example/ops"}]}"#,
+        );
+        reporting_endpoints_header(host, r#"deploys="https://reports.example/deploys""#);
+
+        let body = CustomBody {
+            report_type: "deploy".to_string(),
+            body: serde_json::json!({"version": "1.2.3"}),
+        };
+        submit_to_group(&format!("https://{}", host), "deploys", &body);
+        submit_to_group(&format!("https://{}", host), "ops", &body);
+
+        for endpoint in [
+            "https://reports.example/deploys",
+            "https://reports.example/ops",
+        ] {
+            let mut report = REPORT_QUEUE.try_pop().unwrap();
+            let target = choose_endpoint(&mut report, true).unwrap();
+            assert_eq!(target.endpoint, endpoint);
+
+            let payload = encode(&report, 1.0, &target, &ReportingConfig::default());
+            let json: serde_json::Value = serde_json::from_slice(&payload.body).unwrap();
+            assert_eq!(json[0]["type"], "deploy");
+            assert_eq!(json[0]["body"]["version"], "1.2.3");
+        }
+        assert!(REPORT_QUEUE.try_pop().is_none());
+    }
 }
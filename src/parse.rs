@@ -0,0 +1,253 @@
+use crate::error::{Error, Phase};
+use crate::report::ReportHeader;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// ReceivedReport is a network-error report parsed from the body of a request to a collector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedReport {
+    /// How long ago the report was generated, relative to when it was sent.
+    pub age: Duration,
+    pub url: String,
+    pub user_agent: Option<String>,
+
+    pub referrer: String,
+    pub sampling_fraction: f32,
+    pub server_ip: String,
+    pub protocol: String,
+    pub method: String,
+    pub status_code: usize,
+    pub elapsed_time: Duration,
+    pub phase: Phase,
+    /// The error that occurred, or None if the request succeeded.
+    pub error: Option<Error>,
+    /// Values of the request and response headers the policy asked for, by lowercase name.
+    pub request_headers: BTreeMap<String, Vec<String>>,
+    pub response_headers: BTreeMap<String, Vec<String>>,
+    /// The number of identical reports this report stands for.
+    pub count: u64,
+    /// How long before the report was sent the first and the last of the reports it stands for
+    /// were generated, if it stands for more than one.
+    pub first_seen_age: Option<Duration>,
+    pub last_seen_age: Option<Duration>,
+}
+
+impl ReceivedReport {
+    /// Returns true if the report describes a successful request.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// ParseError describes why a body of reports was rejected.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The body isn't JSON in the shape of a list of reports.
+    Json(serde_json::Error),
+    /// A report's phase isn't one of dns, connection or application.
+    InvalidPhase(String),
+    /// A report's error type isn't a known NEL error type.
+    InvalidErrorType(String),
+    /// A report's sampling fraction is outside of 0..=1.
+    InvalidSamplingFraction(f32),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Json(err) => write!(f, "malformed reports: {}", err),
+            ParseError::InvalidPhase(phase) => write!(f, "invalid phase: {}", phase),
+            ParseError::InvalidErrorType(ty) => write!(f, "invalid error type: {}", ty),
+            ParseError::InvalidSamplingFraction(fraction) => {
+                write!(f, "invalid sampling fraction: {}", fraction)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// parse_reports takes the body of an application/reports+json request and returns the
+/// network-error reports in it. Both the legacy NEL format and the Reporting API v1 format are
+/// accepted. Reports of other types, which may share a batch with network errors, are skipped.
+pub fn parse_reports(body: &[u8]) -> Result<Vec<ReceivedReport>, ParseError> {
+    // Reports are normally delivered as a list, but a lone report object is accepted too.
+    let value: serde_json::Value = serde_json::from_slice(body).map_err(ParseError::Json)?;
+    let values = match value {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .filter(|value| value.get("type").and_then(|ty| ty.as_str()) == Some("network-error"))
+        .map(|value| {
+            serde_json::from_value::<ReportHeader>(value)
+                .map_err(ParseError::Json)
+                .and_then(validate)
+        })
+        .collect()
+}
+
+fn validate(hdr: ReportHeader) -> Result<ReceivedReport, ParseError> {
+    let body = hdr.body;
+
+    let phase = body.phase.parse::<Phase>()?;
+    let error = if body.error_type == "ok" {
+        None
+    } else {
+        match body.error_type.parse::<Error>() {
+            Ok(err) => Some(err),
+            // Types outside the spec, such as those of Error::custom, are kept as they are.
+            Err(_) if is_error_type(&body.error_type) => {
+                Some(Error::custom(phase, body.error_type))
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    if !(0.0..=1.0).contains(&body.sampling_fraction) {
+        return Err(ParseError::InvalidSamplingFraction(body.sampling_fraction));
+    }
+
+    Ok(ReceivedReport {
+        age: Duration::from_millis(hdr.age as u64),
+        url: hdr.url,
+        user_agent: hdr.user_agent,
+
+        referrer: body.referrer,
+        sampling_fraction: body.sampling_fraction,
+        server_ip: body.server_ip,
+        protocol: body.protocol,
+        method: body.method,
+        status_code: body.status_code,
+        elapsed_time: Duration::from_millis(body.elapsed_time as u64),
+        phase,
+        error,
+        request_headers: body.request_headers,
+        response_headers: body.response_headers,
+        count: body.count,
+        first_seen_age: body
+            .first_seen_age
+            .map(|age| Duration::from_millis(age as u64)),
+        last_seen_age: body
+            .last_seen_age
+            .map(|age| Duration::from_millis(age as u64)),
+    })
+}
+
+/// Returns true if `error_type` looks like a NEL error type: dot-separated names made of lowercase
+/// letters, digits and underscores.
+fn is_error_type(error_type: &str) -> bool {
+    error_type.split('.').all(|name| {
+        !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TransportError;
+    use crate::NELReport;
+
+    #[test]
+    fn round_trip() {
+        let mut report = NELReport::new("https://example.com/".to_string());
+        report.set_server_ip(Some("192.0.2.1:443"));
+        report.set_method(Some("GET"));
+        report.set_error(Error::Tcp(TransportError::TimedOut));
+
+        let parsed = parse_reports(report.serialize().as_bytes()).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].url, "https://example.com/");
+        assert_eq!(parsed[0].server_ip, "192.0.2.1");
+        assert_eq!(parsed[0].phase, Phase::Connection);
+        assert_eq!(parsed[0].error, Some(Error::Tcp(TransportError::TimedOut)));
+    }
+
+    #[test]
+    fn round_trips_custom_errors() {
+        let mut report = NELReport::new("https://example.com/".to_string());
+        report.set_error(Error::custom(
+            Phase::Application,
+            "http.response.rejected_by_waf",
+        ));
+
+        let parsed = parse_reports(report.serialize().as_bytes()).unwrap();
+        assert_eq!(
+            parsed[0].error,
+            Some(Error::custom(
+                Phase::Application,
+                "http.response.rejected_by_waf"
+            ))
+        );
+        assert_eq!(parsed[0].phase, Phase::Application);
+    }
+
+    #[test]
+    fn reporting_v1() {
+        let body = r#"[{
+            "age": 10,
+            "type": "network-error",
+            "url": "https://example.com/",
+            "user_agent": "Mozilla/5.0",
+            "body": {
+                "samplingFraction": 0.5,
+                "serverIp": "192.0.2.1",
+                "protocol": "h2",
+                "method": "GET",
+                "statusCode": 200,
+                "elapsedTime": 45,
+                "phase": "application",
+                "type": "ok"
+            }
+        }]"#;
+        let parsed = parse_reports(body.as_bytes()).unwrap();
+        assert!(parsed[0].is_success());
+        assert_eq!(parsed[0].user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(parsed[0].sampling_fraction, 0.5);
+        assert_eq!(parsed[0].elapsed_time, Duration::from_millis(45));
+    }
+
+    #[test]
+    fn skips_other_report_types() {
+        let body = r#"[
+            {"age":0,"type":"csp-violation","url":"https://example.com/",
+                "body":{"blockedURL":"https://evil.example/"}},
+            {"age":0,"type":"network-error","url":"https://example.com/",
+                "body":{"phase":"connection","type":"tcp.refused"}},
+            {"age":0,"type":"deprecation","url":"https://example.com/","body":{}}
+        ]"#;
+        let parsed = parse_reports(body.as_bytes()).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].error, Some(Error::Tcp(TransportError::Refused)));
+
+        let body = r#"{"age":0,"type":"deprecation","url":"https://example.com/","body":{}}"#;
+        assert!(parse_reports(body.as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_reports() {
+        let body = r#"[{"age":0,"type":"network-error","url":"https://example.com/",
+            "body":{"phase":"transport","type":"tcp.timed_out"}}]"#;
+        assert!(matches!(
+            parse_reports(body.as_bytes()),
+            Err(ParseError::InvalidPhase(_))
+        ));
+
+        let body = r#"[{"age":0,"type":"network-error","url":"https://example.com/",
+            "body":{"phase":"connection","type":"TCP timed out"}}]"#;
+        assert!(matches!(
+            parse_reports(body.as_bytes()),
+            Err(ParseError::InvalidErrorType(_))
+        ));
+    }
+}
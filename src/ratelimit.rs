@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Maximum number of per-origin or per-endpoint buckets kept before idle ones are pruned.
+const MAX_BUCKETS: usize = 1024;
+
+/// Total number of reports dropped by rate limiting, across all handlers.
+static LIMITED_REPORTS: AtomicU64 = AtomicU64::new(0);
+
+/// rate_limited_reports returns the number of reports that have been dropped because they
+/// exceeded a configured rate limit.
+pub fn rate_limited_reports() -> u64 {
+    LIMITED_REPORTS.load(Ordering::Relaxed)
+}
+
+/// RateLimit configures a token bucket: up to `burst` reports may be sent at once, and the
+/// bucket refills at `per_second` reports per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+
+    // Reports offered to and admitted by the bucket since it was last full.
+    offered: u64,
+    admitted: u64,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+            offered: 0,
+            admitted: 0,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        let burst = self.limit.burst as f64;
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(burst);
+        self.last_refill = now;
+
+        // Once the bucket is full again nothing is being limited, so start counting afresh.
+        if self.tokens >= burst {
+            self.offered = 0;
+            self.admitted = 0;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.limit.burst as f64
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+        self.offered += 1;
+        self.admitted += 1;
+    }
+
+    /// Returns the fraction of recently offered reports that this bucket let through.
+    fn fraction(&self) -> f32 {
+        if self.offered == 0 {
+            1.0
+        } else {
+            self.admitted as f32 / self.offered as f32
+        }
+    }
+}
+
+/// RateLimiter enforces global, per-origin and per-endpoint limits on outgoing reports.
+pub struct RateLimiter {
+    global: Option<TokenBucket>,
+    origin_limit: Option<RateLimit>,
+    origins: HashMap<String, TokenBucket>,
+    endpoint_limit: Option<RateLimit>,
+    endpoints: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        global: Option<RateLimit>,
+        origin: Option<RateLimit>,
+        endpoint: Option<RateLimit>,
+    ) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            global: global.map(|limit| TokenBucket::new(limit, now)),
+            origin_limit: origin,
+            origins: HashMap::new(),
+            endpoint_limit: endpoint,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    /// Decides whether a report about `origin` may be queued. This is checked as reports are
+    /// submitted, so that one origin can't fill the queue and starve the others.
+    pub fn admit_origin(&mut self, origin: &str, now: Instant) -> bool {
+        match bucket(&mut self.origins, self.origin_limit, origin, now) {
+            Some(bucket) => offer(&mut [bucket], now),
+            None => true,
+        }
+    }
+
+    /// Decides whether a report about `origin` may be sent to `endpoint`. If it may, returns the
+    /// smallest fraction of recently offered reports that any of the limits let through, so that
+    /// it can be folded into the report's sampling fraction.
+    pub fn admit(&mut self, origin: &str, endpoint: &str, now: Instant) -> Option<f32> {
+        let global = self.global.as_mut();
+        let endpoint = bucket(&mut self.endpoints, self.endpoint_limit, endpoint, now);
+        let mut buckets: Vec<&mut TokenBucket> =
+            vec![global, endpoint].into_iter().flatten().collect();
+        if !offer(&mut buckets, now) {
+            return None;
+        }
+        let fraction = buckets
+            .iter()
+            .map(|bucket| bucket.fraction())
+            .fold(1.0, f32::min);
+
+        // The origin's limit was applied when the report was submitted.
+        let origin_fraction = self.origins.get_mut(origin).map_or(1.0, |bucket| {
+            bucket.refill(now);
+            bucket.fraction()
+        });
+        Some(fraction.min(origin_fraction))
+    }
+}
+
+/// Offers a report to a set of buckets, which admit it only if they all have a token. A bucket
+/// counts the report as offered if it admits it or is one of those that rejected it, so that a
+/// bucket's fraction isn't lowered by reports that other buckets dropped.
+fn offer(buckets: &mut [&mut TokenBucket], now: Instant) -> bool {
+    let mut admitted = true;
+    for bucket in buckets.iter_mut() {
+        bucket.refill(now);
+        if !bucket.has_token() {
+            bucket.offered += 1;
+            admitted = false;
+        }
+    }
+    if !admitted {
+        LIMITED_REPORTS.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    for bucket in buckets.iter_mut() {
+        bucket.take();
+    }
+    true
+}
+
+fn bucket<'a>(
+    buckets: &'a mut HashMap<String, TokenBucket>,
+    limit: Option<RateLimit>,
+    key: &str,
+    now: Instant,
+) -> Option<&'a mut TokenBucket> {
+    let limit = limit?;
+    if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+        // Forget buckets that have been idle long enough to refill completely.
+        buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            !bucket.is_full()
+        });
+    }
+    Some(
+        buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(limit, now)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        per_second: 1.0,
+    };
+
+    #[test]
+    fn limits_per_origin() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(None, Some(LIMIT), None);
+        assert!(limiter.admit_origin("a.com", now));
+        assert!(limiter.admit_origin("a.com", now));
+        assert!(!limiter.admit_origin("a.com", now));
+        assert!(limiter.admit_origin("b.com", now));
+        assert_eq!(limiter.admit("b.com", "https://r/", now), Some(1.0));
+
+        // After a second one token is back, and one of the four reports about a.com was dropped.
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.admit_origin("a.com", later));
+        assert_eq!(limiter.admit("a.com", "https://r/", later), Some(0.75));
+    }
+
+    #[test]
+    fn global_limit_applies_to_all_origins() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(Some(LIMIT), None, Some(LIMIT));
+        assert!(limiter.admit("a.com", "https://r1/", now).is_some());
+        assert!(limiter.admit("b.com", "https://r2/", now).is_some());
+        assert!(limiter.admit("c.com", "https://r3/", now).is_none());
+    }
+
+    #[test]
+    fn drops_count_against_rejecting_bucket_only() {
+        let now = Instant::now();
+        let global = RateLimit {
+            burst: 10,
+            per_second: 1.0,
+        };
+        let mut limiter = RateLimiter::new(Some(global), None, Some(LIMIT));
+        assert_eq!(limiter.admit("a.com", "https://r1/", now), Some(1.0));
+        assert_eq!(limiter.admit("a.com", "https://r1/", now), Some(1.0));
+        assert_eq!(limiter.admit("a.com", "https://r1/", now), None);
+
+        // The drop was the endpoint's doing, so the global limit hasn't lowered anyone's fraction.
+        assert_eq!(limiter.admit("b.com", "https://r2/", now), Some(1.0));
+
+        // One of three reports to r1 was dropped, which is counted once.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.admit("a.com", "https://r1/", later), Some(0.75));
+    }
+}
@@ -1,11 +1,15 @@
-use crate::error::Error;
+use crate::error::{DnsError, Error, Phase, Stage};
+use crate::generic::Report;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::{Duration, Instant};
 
 /// NELReport captures all of the internal information we need about an error that occurred.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct NELReport {
     captured: Instant,
+    last_seen: Instant,
+    count: u64,
 
     pub url: String,
     pub referer: String,
@@ -14,8 +18,11 @@ pub struct NELReport {
     pub method: String,
     pub status_code: usize,
     pub elapsed_time: Duration,
-    phase: String,
-    error_type: String,
+    error: Option<Error>,
+    phase: Phase,
+    stage: Option<Stage>,
+    request_headers: Headers,
+    response_headers: Headers,
 
     /// Overrides the URL host for the purpose of choosing where to submit the report.
     pub host_override: Option<String>,
@@ -23,8 +30,11 @@ pub struct NELReport {
 
 impl NELReport {
     pub fn new(url: String) -> Self {
+        let now = Instant::now();
         NELReport {
-            captured: Instant::now(),
+            captured: now,
+            last_seen: now,
+            count: 1,
 
             url,
             referer: "".to_string(),
@@ -33,8 +43,11 @@ impl NELReport {
             method: "".to_string(),
             status_code: 0,
             elapsed_time: Default::default(),
-            phase: "".to_string(),
-            error_type: "".to_string(),
+            error: None,
+            phase: Phase::Application,
+            stage: None,
+            request_headers: Headers::new(),
+            response_headers: Headers::new(),
 
             host_override: None,
         }
@@ -42,7 +55,31 @@ impl NELReport {
 
     /// Returns true if no error has been attached to the report.
     pub fn is_success(&self) -> bool {
-        self.phase.is_empty()
+        self.error.is_none()
+    }
+
+    /// Returns the error attached to the report, if any.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the phase the error happened in, which is the application phase for successful
+    /// requests.
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Returns the number of identical reports this report stands for.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    /// Returns when the first of the aggregated reports was captured.
+    pub fn first_seen(&self) -> Instant {
+        self.captured
+    }
+    /// Returns when the most recent of the aggregated reports was captured.
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
     }
 
     pub fn set_referer<T: ToString>(&mut self, val: Option<T>) {
@@ -75,19 +112,155 @@ impl NELReport {
 
     pub fn set_error<T: Into<Error>>(&mut self, err: T) {
         let mut err: Error = err.into();
-        if self.protocol == "wireguard" && err.class == "tcp" {
-            err.class = "udp".to_string();
+        // These protocols run over UDP, so transport errors from the OS are UDP errors.
+        if self.protocol == "wireguard" || self.protocol == "h3" {
+            if let Error::Tcp(transport) = err {
+                err = Error::Udp(transport);
+            }
+        }
+        self.error = Some(err);
+        self.update_phase();
+    }
+
+    /// Captures the request's headers. Only those named in the origin's NEL policy are kept when
+    /// the report is submitted.
+    pub fn set_request_headers<I, K, V>(&mut self, headers: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        self.request_headers = collect_headers(headers);
+    }
+    /// Captures the response's headers. Only those named in the origin's NEL policy are kept when
+    /// the report is submitted.
+    pub fn set_response_headers<I, K, V>(&mut self, headers: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        self.response_headers = collect_headers(headers);
+    }
+
+    /// Returns the captured request headers, by lowercase name.
+    pub fn request_headers(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.request_headers
+    }
+    /// Returns the captured response headers, by lowercase name.
+    pub fn response_headers(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.response_headers
+    }
+
+    /// Drops every captured header that the policy didn't ask for.
+    pub(crate) fn retain_headers(&mut self, request: &[String], response: &[String]) {
+        self.request_headers
+            .retain(|name, _| request.contains(name));
+        self.response_headers
+            .retain(|name, _| response.contains(name));
+    }
+
+    /// Records how far through its lifecycle the request got, so that an error's phase is where
+    /// it actually happened.
+    pub fn set_stage(&mut self, stage: Stage) {
+        self.stage = Some(stage);
+        self.update_phase();
+    }
+
+    fn update_phase(&mut self) {
+        self.phase = match (&self.error, self.stage) {
+            (None, _) => Phase::Application,
+            (Some(err), Some(stage)) => err.phase_at(stage),
+            (Some(err), None) => err.phase(),
+        };
+    }
+
+    /// Sets an error from an HTTP/3 request, which also sets the report's protocol to h3.
+    pub fn set_h3_error<T: Into<Error>>(&mut self, err: T) {
+        self.protocol = "h3".to_string();
+        self.set_error(err);
+    }
+
+    /// Replaces the report with a dns.address_changed error, clearing the fields that say
+    /// anything about the request, for reports about a server that didn't set the policy.
+    pub(crate) fn downgrade(&mut self) {
+        self.error = Some(Error::Dns(DnsError::AddressChanged));
+        self.phase = Phase::Dns;
+        self.stage = None;
+        self.status_code = 0;
+        self.elapsed_time = Duration::ZERO;
+        self.request_headers.clear();
+        self.response_headers.clear();
+    }
+
+    /// Returns the fields that identify duplicate reports for aggregation.
+    pub(crate) fn aggregation_key(&self) -> AggregationKey {
+        AggregationKey {
+            url: self.url.clone(),
+            server_ip: self.server_ip.clone(),
+            protocol: self.protocol.clone(),
+            method: self.method.clone(),
+            status_code: self.status_code,
+            error: self.error.clone(),
+            phase: self.phase,
+            request_headers: self.request_headers.clone(),
+            response_headers: self.response_headers.clone(),
+            host_override: self.host_override.clone(),
         }
-        self.phase = err.phase();
-        self.error_type = err.to_string();
+    }
+
+    /// Folds a duplicate report into this one.
+    pub(crate) fn merge(&mut self, other: &NELReport) {
+        self.count += other.count;
+        self.captured = self.captured.min(other.captured);
+        self.last_seen = self.last_seen.max(other.last_seen);
     }
 
     pub fn serialize(&self) -> String {
-        let hdrs = vec![ReportHeader::from(self)];
-        serde_json::to_string(&hdrs).unwrap()
+        self.serialize_as(1.0, ReportFormat::Legacy, None)
+    }
+
+    /// Serializes the report in the given format, recording the fraction of reports like it that
+    /// are being sent.
+    pub(crate) fn serialize_as(
+        &self,
+        sampling_fraction: f32,
+        format: ReportFormat,
+        user_agent: Option<&str>,
+    ) -> String {
+        let mut hdr = ReportHeader::from(self);
+        hdr.body.sampling_fraction = sampling_fraction;
+        match format {
+            ReportFormat::Legacy => serde_json::to_string(&vec![hdr]).unwrap(),
+            ReportFormat::V1 => {
+                serde_json::to_string(&vec![ReportHeaderV1::new(&hdr, user_agent)]).unwrap()
+            }
+        }
     }
 }
 
+/// Header values by lowercase header name.
+type Headers = BTreeMap<String, Vec<String>>;
+
+/// Groups headers by lowercase name, skipping values that aren't UTF-8.
+fn collect_headers<I, K, V>(headers: I) -> Headers
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<[u8]>,
+{
+    let mut collected = Headers::new();
+    for (name, value) in headers {
+        if let Ok(value) = std::str::from_utf8(value.as_ref()) {
+            collected
+                .entry(name.as_ref().to_ascii_lowercase())
+                .or_default()
+                .push(value.to_string());
+        }
+    }
+    collected
+}
+
 fn opt_to_string<T: ToString>(input: Option<T>) -> String {
     match input {
         None => "".to_string(),
@@ -95,63 +268,248 @@ fn opt_to_string<T: ToString>(input: Option<T>) -> String {
     }
 }
 
+/// AggregationKey holds the fields two reports must share to be collapsed into one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AggregationKey {
+    url: String,
+    server_ip: String,
+    protocol: String,
+    method: String,
+    status_code: usize,
+    error: Option<Error>,
+    phase: Phase,
+    request_headers: Headers,
+    response_headers: Headers,
+    host_override: Option<String>,
+}
+
+/// ReportFormat is the format reports are serialized in for an endpoint group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportFormat {
+    /// The format from the original NEL spec, with snake_case body fields.
+    Legacy,
+    /// The Reporting API v1 format, with camelCase body fields and the user agent.
+    V1,
+}
+
+/// QueuedReport is a report waiting to be submitted.
+pub(crate) enum QueuedReport {
+    Network(NELReport),
+    Other(Report),
+}
+
+impl QueuedReport {
+    pub(crate) fn url(&self) -> &str {
+        match self {
+            QueuedReport::Network(report) => &report.url,
+            QueuedReport::Other(report) => &report.url,
+        }
+    }
+
+    pub(crate) fn host_override(&self) -> Option<&str> {
+        match self {
+            QueuedReport::Network(report) => report.host_override.as_deref(),
+            QueuedReport::Other(report) => report.host_override.as_deref(),
+        }
+    }
+
+    /// Serializes the report in the given format. The sampling fraction only applies to NEL
+    /// reports.
+    pub(crate) fn serialize_as(
+        &self,
+        sampling_fraction: f32,
+        format: ReportFormat,
+        user_agent: Option<&str>,
+    ) -> String {
+        match self {
+            QueuedReport::Network(report) => {
+                report.serialize_as(sampling_fraction, format, user_agent)
+            }
+            QueuedReport::Other(report) => report.serialize_as(format, user_agent),
+        }
+    }
+}
+
 /// FailedReport wraps a report with the time we tried and failed to submit it to the NEL endpoint.
 pub struct FailedReport {
     pub last_try: Instant,
-    pub original: NELReport,
+    pub(crate) original: QueuedReport,
+    pub sampling_fraction: f32,
 }
 
 /// ReportHeader is the structure we serialize and submit to the NEL endpoint.
 #[derive(Serialize, Deserialize)]
-struct ReportHeader {
-    age: usize,
+pub(crate) struct ReportHeader {
+    pub(crate) age: usize,
     #[serde(rename = "type")]
-    report_type: String,
-    url: String,
-    body: ReportBody,
+    pub(crate) report_type: String,
+    pub(crate) url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) user_agent: Option<String>,
+    pub(crate) body: ReportBody,
 }
 
+/// ReportBody is the network-error specific part of a report. When parsing, the camelCase field
+/// names used by Reporting API v1 are accepted along with the legacy snake_case ones.
 #[derive(Serialize, Deserialize)]
-struct ReportBody {
-    referrer: String,
-    sampling_fraction: f32,
-    server_ip: String,
-    protocol: String,
-    method: String,
-    status_code: usize,
-    elapsed_time: u128,
-    phase: String,
+pub(crate) struct ReportBody {
+    #[serde(default)]
+    pub(crate) referrer: String,
+    #[serde(default = "default_sampling_fraction", alias = "samplingFraction")]
+    pub(crate) sampling_fraction: f32,
+    #[serde(default, alias = "serverIp")]
+    pub(crate) server_ip: String,
+    #[serde(default)]
+    pub(crate) protocol: String,
+    #[serde(default)]
+    pub(crate) method: String,
+    #[serde(default, alias = "statusCode")]
+    pub(crate) status_code: usize,
+    #[serde(default, alias = "elapsedTime")]
+    pub(crate) elapsed_time: u128,
+    pub(crate) phase: String,
     #[serde(rename = "type")]
-    error_type: String,
+    pub(crate) error_type: String,
+    #[serde(
+        default,
+        alias = "requestHeaders",
+        skip_serializing_if = "Headers::is_empty"
+    )]
+    pub(crate) request_headers: Headers,
+    #[serde(
+        default,
+        alias = "responseHeaders",
+        skip_serializing_if = "Headers::is_empty"
+    )]
+    pub(crate) response_headers: Headers,
+    #[serde(default = "default_count", skip_serializing_if = "is_single")]
+    pub(crate) count: u64,
+    /// How many milliseconds before the report was sent the first and the last of the reports it
+    /// aggregates were captured. Only set if it aggregates more than one.
+    #[serde(
+        default,
+        alias = "firstSeenAge",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) first_seen_age: Option<usize>,
+    #[serde(
+        default,
+        alias = "lastSeenAge",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) last_seen_age: Option<usize>,
+}
+
+const fn default_sampling_fraction() -> f32 {
+    1.0
+}
+
+const fn default_count() -> u64 {
+    1
+}
+
+fn is_single(count: &u64) -> bool {
+    *count == 1
+}
+
+/// Returns how many milliseconds ago an instant was.
+fn age(at: Instant) -> usize {
+    Instant::now()
+        .checked_duration_since(at)
+        .unwrap_or_else(|| Duration::from_secs(0))
+        .as_millis() as usize
 }
 
 impl From<&NELReport> for ReportHeader {
     fn from(report: &NELReport) -> Self {
+        let aggregated = report.count > 1;
         ReportHeader {
-            age: Instant::now()
-                .checked_duration_since(report.captured)
-                .unwrap_or_else(|| Duration::from_secs(0))
-                .as_millis() as usize,
+            age: age(report.last_seen),
             report_type: "network-error".to_string(),
             url: report.url.clone(),
+            user_agent: None,
             body: ReportBody {
                 referrer: report.referer.clone(),
-                sampling_fraction: 1.0, // TODO: Correctly populate.
+                sampling_fraction: 1.0,
                 server_ip: report.server_ip.clone(),
                 protocol: report.protocol.clone(),
                 method: report.method.clone(),
                 status_code: report.status_code,
                 elapsed_time: report.elapsed_time.as_millis(),
-                phase: if report.is_success() {
-                    "application".to_string()
-                } else {
-                    report.phase.to_string()
-                },
-                error_type: if report.is_success() {
-                    "ok".to_string()
-                } else {
-                    report.error_type.clone()
+                phase: report.phase.to_string(),
+                error_type: match &report.error {
+                    None => "ok".to_string(),
+                    Some(err) => err.to_string(),
                 },
+                request_headers: report.request_headers.clone(),
+                response_headers: report.response_headers.clone(),
+                count: report.count,
+                first_seen_age: Some(age(report.captured)).filter(|_| aggregated),
+                last_seen_age: Some(age(report.last_seen)).filter(|_| aggregated),
+            },
+        }
+    }
+}
+
+/// ReportHeaderV1 is a report in the Reporting API v1 format, which always has a user agent.
+#[derive(Serialize)]
+struct ReportHeaderV1<'a> {
+    age: usize,
+    #[serde(rename = "type")]
+    report_type: &'a str,
+    url: &'a str,
+    user_agent: &'a str,
+    body: ReportBodyV1<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportBodyV1<'a> {
+    referrer: &'a str,
+    sampling_fraction: f32,
+    server_ip: &'a str,
+    protocol: &'a str,
+    method: &'a str,
+    status_code: usize,
+    elapsed_time: u128,
+    phase: &'a str,
+    #[serde(rename = "type")]
+    error_type: &'a str,
+    #[serde(skip_serializing_if = "Headers::is_empty")]
+    request_headers: &'a Headers,
+    #[serde(skip_serializing_if = "Headers::is_empty")]
+    response_headers: &'a Headers,
+    #[serde(skip_serializing_if = "is_single")]
+    count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_seen_age: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen_age: Option<usize>,
+}
+
+impl<'a> ReportHeaderV1<'a> {
+    fn new(hdr: &'a ReportHeader, user_agent: Option<&'a str>) -> Self {
+        let body = &hdr.body;
+        ReportHeaderV1 {
+            age: hdr.age,
+            report_type: &hdr.report_type,
+            url: &hdr.url,
+            user_agent: user_agent.or(hdr.user_agent.as_deref()).unwrap_or(""),
+            body: ReportBodyV1 {
+                referrer: &body.referrer,
+                sampling_fraction: body.sampling_fraction,
+                server_ip: &body.server_ip,
+                protocol: &body.protocol,
+                method: &body.method,
+                status_code: body.status_code,
+                elapsed_time: body.elapsed_time,
+                phase: &body.phase,
+                error_type: &body.error_type,
+                request_headers: &body.request_headers,
+                response_headers: &body.response_headers,
+                count: body.count,
+                first_seen_age: body.first_seen_age,
+                last_seen_age: body.last_seen_age,
             },
         }
     }